@@ -0,0 +1,489 @@
+//   Announcement formats:
+// - https://www.rfc-editor.org/rfc/rfc6763 (DNS-SD over mDNS)
+// - https://openconnectivity.org/upnp-specs/UPnP-arch-DeviceArchitecture-v2.0.pdf (SSDP)
+
+use std::{collections::HashSet, fmt::Display, str::FromStr, time::Duration};
+
+use dns_parser::{Packet, RData, ResourceRecord};
+
+use crate::cache::{DeviceDetails, DeviceEntry, MacCache};
+use crate::cache_logger::DeviceCounts;
+use crate::chatter::{UdpFrame, MDNS_PORT};
+use crate::classify::DeviceClass;
+use crate::randomized::{is_randomized, replaced_macs};
+
+pub const SSDP_PORT: u16 = 1900;
+
+// TXT keys holding a model string: Google Cast, Apple device info, AirPlay, printers
+const MODEL_TXT_KEYS: [&str; 4] = ["md", "model", "am", "ty"];
+
+/// Announcements devices make about themselves that are used to describe them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AnnouncementSources {
+    pub mdns: bool,
+    pub ssdp: bool,
+}
+
+impl AnnouncementSources {
+    pub fn is_empty(&self) -> bool {
+        !self.mdns && !self.ssdp
+    }
+
+    /// Returns the details announced in datagram, if it is an announcement of an enabled source
+    pub fn details(&self, datagram: &UdpFrame) -> Option<DeviceDetails> {
+        if self.mdns && datagram.source_port == MDNS_PORT {
+            return mdns_details(&datagram.payload);
+        }
+
+        if self.ssdp
+            && (datagram.source_port == SSDP_PORT || datagram.destination_port == SSDP_PORT)
+        {
+            return ssdp_details(&datagram.payload);
+        }
+
+        None
+    }
+}
+
+impl FromStr for AnnouncementSources {
+    type Err = String;
+
+    /// Parses a comma separated list of mdns and ssdp
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sources = Self::default();
+
+        for source in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match source {
+                "mdns" => sources.mdns = true,
+                "ssdp" => sources.ssdp = true,
+                _ => return Err(format!("unknown announcement source: {source}")),
+            }
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Display for AnnouncementSources {
+    /// Formats the sources as parsed, e.g. mdns,ssdp
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sources = [(self.mdns, "mdns"), (self.ssdp, "ssdp")];
+
+        let enabled: Vec<&str> = sources
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, s)| *s)
+            .collect();
+        write!(f, "{}", enabled.join(","))
+    }
+}
+
+/// Devices that are fixed infrastructure and never counted, e.g. printers or smart TVs
+///
+/// A device is excluded once it announced one of the service types, a model containing
+/// one of the model patterns, has a vendor containing one of the vendor patterns
+/// (both case insensitive) or is of one of the classes.
+#[derive(Clone, Debug, Default)]
+pub struct ExclusionRules {
+    /// mDNS service types (_googlecast._tcp) or SSDP types
+    /// (urn:schemas-upnp-org:device:MediaRenderer:1)
+    pub service_types: Vec<String>,
+    pub model_patterns: Vec<String>,
+    pub vendor_patterns: Vec<String>,
+    pub classes: Vec<DeviceClass>,
+}
+
+impl ExclusionRules {
+    pub fn new(
+        service_types: Vec<String>,
+        model_patterns: Vec<String>,
+        vendor_patterns: Vec<String>,
+        classes: Vec<DeviceClass>,
+    ) -> Self {
+        Self {
+            service_types: service_types
+                .iter()
+                .map(|service| String::from(service.trim()))
+                .collect(),
+            model_patterns: lowercase_patterns(&model_patterns),
+            vendor_patterns: lowercase_patterns(&vendor_patterns),
+            classes,
+        }
+    }
+
+    /// Whether a device is excluded from the count
+    pub fn excludes(&self, entry: &DeviceEntry) -> bool {
+        let details = &entry.details;
+
+        let excluded_service = details
+            .services
+            .iter()
+            .any(|service| self.service_types.contains(service));
+
+        excluded_service
+            || self.classes.contains(&entry.class)
+            || contains_any(details.model.as_deref(), &self.model_patterns)
+            || contains_any(entry.vendor.as_deref(), &self.vendor_patterns)
+    }
+
+    /// Counts the cached devices that are not excluded
+    /// With a dedup window, devices replaced by a randomized mac within it are not counted.
+    pub fn tally(&self, cache: &MacCache, dedup_window: Option<Duration>) -> DeviceCounts {
+        let replaced = match dedup_window {
+            Some(window) => replaced_macs(cache, window),
+            None => HashSet::new(),
+        };
+
+        let mut counts = DeviceCounts::default();
+
+        for (mac, entry) in cache.iter() {
+            if self.excludes(entry) || replaced.contains(mac) {
+                continue;
+            }
+
+            counts.add(entry.class, entry.vendor.as_deref(), is_randomized(mac));
+        }
+
+        counts
+    }
+}
+
+fn lowercase_patterns(patterns: &[String]) -> Vec<String> {
+    patterns
+        .iter()
+        .map(|pattern| pattern.trim().to_lowercase())
+        .collect()
+}
+
+fn contains_any(value: Option<&str>, patterns: &[String]) -> bool {
+    value.map_or(false, |value| {
+        let value = value.to_lowercase();
+        patterns
+            .iter()
+            .any(|pattern| value.contains(pattern.as_str()))
+    })
+}
+
+// Only responses are announcements, queries describe what the sender looks for
+fn mdns_details(payload: &[u8]) -> Option<DeviceDetails> {
+    let packet = Packet::parse(payload).ok()?;
+
+    if packet.header.query {
+        return None;
+    }
+
+    let mut details = DeviceDetails::default();
+
+    for record in packet.answers.iter().chain(packet.additional.iter()) {
+        describe_record(record, &mut details);
+    }
+
+    Some(details).filter(|details| !details.is_empty())
+}
+
+fn describe_record(record: &ResourceRecord, details: &mut DeviceDetails) {
+    let name = record.name.to_string();
+
+    match &record.data {
+        RData::A(_) | RData::AAAA(_) => {
+            if let Some(hostname) = local_name(&name) {
+                details.hostname = Some(hostname);
+            }
+        }
+        RData::SRV(srv) => {
+            if let Some(hostname) = local_name(&srv.target.to_string()) {
+                details.hostname = Some(hostname);
+            }
+            details.add_service(service_type(&name));
+        }
+        RData::PTR(_) => details.add_service(service_type(&name)),
+        RData::TXT(txt) => {
+            details.add_service(service_type(&name));
+
+            for entry in txt.iter() {
+                let entry = String::from_utf8_lossy(entry);
+                if let Some((key, value)) = entry.split_once('=') {
+                    if MODEL_TXT_KEYS.contains(&key.to_lowercase().as_str()) {
+                        if let Some(model) = clean_text(value) {
+                            details.model = Some(model);
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// Host name without the .local domain
+fn local_name(name: &str) -> Option<String> {
+    let name = name.trim_end_matches('.');
+    let name = name.strip_suffix(".local").unwrap_or(name);
+    clean_text(name)
+}
+
+// Service type at the end of a DNS-SD name, e.g. _ipp._tcp in Printer._ipp._tcp.local
+fn service_type(name: &str) -> Option<String> {
+    let name = name.trim_end_matches('.');
+    let name = name.strip_suffix(".local")?;
+    let mut labels = name.rsplit('.');
+
+    let protocol = labels.next()?;
+    let service = labels.next()?;
+
+    if (protocol == "_tcp" || protocol == "_udp") && service.starts_with('_') {
+        Some(format!("{service}.{protocol}"))
+    } else {
+        None
+    }
+}
+
+// NOTIFY announcements and M-SEARCH responses, searches and byebyes are skipped
+fn ssdp_details(payload: &[u8]) -> Option<DeviceDetails> {
+    let message = String::from_utf8_lossy(payload);
+    let mut lines = message.lines();
+
+    let start_line = lines.next()?;
+    if !start_line.starts_with("NOTIFY") && !start_line.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+
+    let mut details = DeviceDetails::default();
+
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim().to_uppercase(), value.trim()),
+            None => continue,
+        };
+
+        match name.as_str() {
+            "NTS" if value.eq_ignore_ascii_case("ssdp:byebye") => return None,
+            "SERVER" => details.model = clean_text(value),
+            // root device and uuid notifications carry no type
+            "NT" | "ST" if value.starts_with("urn:") => details.add_service(clean_text(value)),
+            _ => {}
+        }
+    }
+
+    Some(details).filter(|details| !details.is_empty())
+}
+
+// Control characters are dropped, the text ends up in logs
+fn clean_text(text: &str) -> Option<String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let text = text.trim();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(String::from(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use pnet_datalink::MacAddr;
+
+    use super::*;
+
+    const TYPE_A: u16 = 1;
+    const TYPE_PTR: u16 = 12;
+    const TYPE_TXT: u16 = 16;
+    const TYPE_SRV: u16 = 33;
+
+    /// DNS name as length prefixed labels
+    fn name(name: &str) -> Vec<u8> {
+        let mut encoded = vec![];
+        for label in name.split('.') {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    fn record(owner: &str, rtype: u16, data: &[u8]) -> Vec<u8> {
+        [
+            name(owner),
+            rtype.to_be_bytes().to_vec(),
+            // IN class, with the cache flush bit
+            vec![0x80, 0x01],
+            120u32.to_be_bytes().to_vec(),
+            (data.len() as u16).to_be_bytes().to_vec(),
+            data.to_vec(),
+        ]
+        .concat()
+    }
+
+    /// mDNS message with records as answers, a response unless query
+    fn message(query: bool, records: &[Vec<u8>]) -> Vec<u8> {
+        let flags: u16 = if query { 0 } else { 0x8400 };
+        [
+            vec![0, 0],
+            flags.to_be_bytes().to_vec(),
+            vec![0, 0],
+            (records.len() as u16).to_be_bytes().to_vec(),
+            vec![0, 0, 0, 0],
+            records.concat(),
+        ]
+        .concat()
+    }
+
+    fn cast_announcement() -> Vec<u8> {
+        let srv = [vec![0, 0, 0, 0, 0x1f, 0x49], name("Living-Room.local")].concat();
+        let txt = [&[13][..], b"md=Chromecast", &[6], b"id=abc"].concat();
+
+        message(
+            false,
+            &[
+                record(
+                    "_googlecast._tcp.local",
+                    TYPE_PTR,
+                    &name("Living-Room._googlecast._tcp.local"),
+                ),
+                record("Living-Room._googlecast._tcp.local", TYPE_SRV, &srv),
+                record("Living-Room._googlecast._tcp.local", TYPE_TXT, &txt),
+                record("Living-Room.local", TYPE_A, &[10, 0, 0, 7]),
+            ],
+        )
+    }
+
+    fn datagram(source_port: u16, destination_port: u16, payload: &[u8]) -> UdpFrame {
+        UdpFrame {
+            eth_source: MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55),
+            source_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)),
+            source_port,
+            destination_port,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn mdns_response_describes_device() {
+        let details = mdns_details(&cast_announcement()).unwrap();
+
+        assert_eq!(details.hostname.as_deref(), Some("Living-Room"));
+        assert_eq!(details.model.as_deref(), Some("Chromecast"));
+        assert_eq!(details.services, vec![String::from("_googlecast._tcp")]);
+    }
+
+    #[test]
+    fn mdns_queries_and_empty_responses_are_ignored() {
+        let query = message(
+            true,
+            &[record(
+                "_googlecast._tcp.local",
+                TYPE_PTR,
+                &name("a._googlecast._tcp.local"),
+            )],
+        );
+        assert!(mdns_details(&query).is_none());
+
+        assert!(mdns_details(&message(false, &[])).is_none());
+
+        // records that say nothing about the device
+        let unrelated = message(
+            false,
+            &[record(
+                "7.0.0.10.in-addr.arpa",
+                TYPE_PTR,
+                &name("Living-Room.local"),
+            )],
+        );
+        assert!(mdns_details(&unrelated).is_none());
+    }
+
+    #[test]
+    fn malformed_mdns_is_ignored() {
+        let announcement = cast_announcement();
+
+        for len in [0, 5, 12, 20, announcement.len() - 1] {
+            assert!(mdns_details(&announcement[..len]).is_none(), "{len}");
+        }
+
+        // more answers than the message holds
+        let mut overcounted = announcement.clone();
+        overcounted[7] = 9;
+        assert!(mdns_details(&overcounted).is_none());
+
+        // name label running past the end of the message
+        let mut overlong_label = message(false, &[record("a.local", TYPE_A, &[10, 0, 0, 7])]);
+        overlong_label[12] = 60;
+        assert!(mdns_details(&overlong_label).is_none());
+
+        assert!(mdns_details(&[0xff; 64]).is_none());
+    }
+
+    #[test]
+    fn ssdp_notify_describes_device() {
+        let notify = b"NOTIFY * HTTP/1.1\r\n\
+            HOST: 239.255.255.250:1900\r\n\
+            NT: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\
+            NTS: ssdp:alive\r\n\
+            server: Linux/4.9 UPnP/1.0 \x1bTV/2.0\r\n\r\n";
+
+        let details = ssdp_details(notify).unwrap();
+        assert_eq!(details.model.as_deref(), Some("Linux/4.9 UPnP/1.0 TV/2.0"));
+        assert_eq!(
+            details.services,
+            vec![String::from("urn:schemas-upnp-org:device:MediaRenderer:1")]
+        );
+
+        let response = b"HTTP/1.1 200 OK\r\n\
+            ST: urn:schemas-upnp-org:device:Printer:1\r\n\r\n";
+        assert_eq!(
+            ssdp_details(response).unwrap().services,
+            vec![String::from("urn:schemas-upnp-org:device:Printer:1")]
+        );
+    }
+
+    #[test]
+    fn ssdp_searches_byebyes_and_malformed_messages_are_ignored() {
+        let search = b"M-SEARCH * HTTP/1.1\r\n\
+            ST: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\r\n";
+        assert!(ssdp_details(search).is_none());
+
+        let byebye = b"NOTIFY * HTTP/1.1\r\n\
+            NT: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\
+            NTS: ssdp:byebye\r\n\r\n";
+        assert!(ssdp_details(byebye).is_none());
+
+        // root device notification without a type or server
+        let root = b"NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\n\r\n";
+        assert!(ssdp_details(root).is_none());
+
+        for malformed in [
+            &b""[..],
+            b"NOTIFY",
+            b"NOTIFY * HTTP/1.1\r\nSERVER",
+            b"NOTIFY * HTTP/1.1\r\nSERVER:\x01\x02\r\n",
+            &[0xff, 0xfe, 0x00, 0x80],
+        ] {
+            assert!(ssdp_details(malformed).is_none(), "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn announcements_are_read_from_enabled_sources_only() {
+        let mdns = datagram(MDNS_PORT, MDNS_PORT, &cast_announcement());
+        let ssdp = datagram(
+            SSDP_PORT,
+            50000,
+            b"HTTP/1.1 200 OK\r\nSERVER: Printer/1.0\r\n\r\n",
+        );
+
+        let both: AnnouncementSources = "mdns,ssdp".parse().unwrap();
+        assert!(both.details(&mdns).is_some());
+        assert!(both.details(&ssdp).is_some());
+
+        let mdns_only: AnnouncementSources = "mdns".parse().unwrap();
+        assert!(mdns_only.details(&ssdp).is_none());
+
+        let ssdp_only: AnnouncementSources = "ssdp".parse().unwrap();
+        assert!(ssdp_only.details(&mdns).is_none());
+
+        assert!("mdns,upnp".parse::<AnnouncementSources>().is_err());
+    }
+}
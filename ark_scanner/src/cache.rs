@@ -1,33 +1,283 @@
 use std::{
     collections::{hash_map::Iter, HashMap},
-    time::Instant,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant, SystemTime},
 };
 
 use pnet_datalink::MacAddr;
 
+use crate::classify::DeviceClass;
+
+// Oldest addresses are forgotten past this many
+const MAX_IP_HISTORY: usize = 16;
+const MAX_IPV6_ADDRESSES: usize = 16;
+const MAX_SERVICES: usize = 16;
+
+/// A single sighting of a device
+pub struct Sighting {
+    /// Address the device was seen with, if any
+    pub ip: Option<IpAddr>,
+    /// Name of the interface the device was seen on
+    pub interface: String,
+    /// Monotonic time of the sighting, used for expiry
+    pub at: Instant,
+    /// Wall-clock time of the sighting
+    pub wall_time: SystemTime,
+    /// Details the device announced about itself
+    pub details: DeviceDetails,
+}
+
+impl Sighting {
+    /// Sighting happening now
+    pub fn now(ip: Option<IpAddr>, interface: &str) -> Self {
+        Self {
+            ip,
+            interface: String::from(interface),
+            at: Instant::now(),
+            wall_time: SystemTime::now(),
+            details: DeviceDetails::default(),
+        }
+    }
+
+    pub fn with_details(mut self, details: DeviceDetails) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Names and other details a device announced about itself, e.g. in DHCP requests
+/// or mDNS and SSDP announcements
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceDetails {
+    pub hostname: Option<String>,
+    pub vendor_class: Option<String>,
+    /// DHCP parameter request list
+    pub dhcp_fingerprint: Option<String>,
+    /// Model string, e.g. 'Chromecast' or an SSDP server header
+    pub model: Option<String>,
+    /// Service types the device offers, e.g. '_ipp._tcp', sorted
+    pub services: Vec<String>,
+}
+
+impl DeviceDetails {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Adds service to the offered services if it is not known yet
+    pub fn add_service(&mut self, service: Option<String>) {
+        let service = match service {
+            Some(service) => service,
+            None => return,
+        };
+
+        if let Err(index) = self.services.binary_search(&service) {
+            if self.services.len() < MAX_SERVICES {
+                self.services.insert(index, service);
+            }
+        }
+    }
+
+    // Newly announced details replace older ones, missing ones are kept
+    fn merge(&mut self, newer: DeviceDetails) {
+        if newer.hostname.is_some() {
+            self.hostname = newer.hostname;
+        }
+        if newer.vendor_class.is_some() {
+            self.vendor_class = newer.vendor_class;
+        }
+        if newer.dhcp_fingerprint.is_some() {
+            self.dhcp_fingerprint = newer.dhcp_fingerprint;
+        }
+        if newer.model.is_some() {
+            self.model = newer.model;
+        }
+        for service in newer.services {
+            self.add_service(Some(service));
+        }
+    }
+}
+
+/// Period during which a device used an address
+#[derive(Clone, Debug)]
+pub struct IpBinding {
+    pub ip: Ipv4Addr,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// Everything known about a device, keyed by its mac address in [`MacCache`]
+#[derive(Clone, Debug)]
+pub struct DeviceEntry {
+    /// IPv4 addresses the device used, the current one last
+    pub ip_history: Vec<IpBinding>,
+    /// IPv6 addresses the device was seen with, the most recent last
+    pub ipv6_addresses: Vec<Ipv6Addr>,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// Monotonic time of the last sighting, used for expiry
+    pub last_seen_at: Instant,
+    /// Number of times the device answered or announced itself
+    pub reply_count: u64,
+    /// Interface the device was last seen on
+    pub interface: String,
+    pub details: DeviceDetails,
+    /// Vendor of the mac address, None if unknown or locally administered
+    pub vendor: Option<String>,
+    pub class: DeviceClass,
+}
+
+impl DeviceEntry {
+    fn new(sighting: &Sighting) -> Self {
+        Self {
+            ip_history: vec![],
+            ipv6_addresses: vec![],
+            first_seen: sighting.wall_time,
+            last_seen: sighting.wall_time,
+            last_seen_at: sighting.at,
+            reply_count: 0,
+            interface: sighting.interface.clone(),
+            details: DeviceDetails::default(),
+            vendor: None,
+            class: DeviceClass::Unknown,
+        }
+    }
+
+    /// IPv4 address the device currently uses
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        self.ip_history.last().map(|binding| binding.ip)
+    }
+
+    /// How long the device has been present, between first and last sighting
+    pub fn dwell_time(&self) -> Duration {
+        self.last_seen
+            .duration_since(self.first_seen)
+            .unwrap_or_default()
+    }
+
+    fn record(&mut self, sighting: Sighting) {
+        self.last_seen = sighting.wall_time;
+        self.last_seen_at = sighting.at;
+        self.reply_count += 1;
+        self.interface = sighting.interface;
+        self.details.merge(sighting.details);
+
+        let ip = match sighting.ip {
+            Some(IpAddr::V4(ip)) => ip,
+            Some(IpAddr::V6(ip)) => {
+                self.ipv6_addresses.retain(|addr| *addr != ip);
+                if self.ipv6_addresses.len() == MAX_IPV6_ADDRESSES {
+                    self.ipv6_addresses.remove(0);
+                }
+                self.ipv6_addresses.push(ip);
+                return;
+            }
+            None => return,
+        };
+
+        match self.ip_history.last_mut() {
+            Some(binding) if binding.ip == ip => binding.last_seen = sighting.wall_time,
+            _ => {
+                // a device moving back to an old address becomes its current binding again
+                self.ip_history.retain(|binding| binding.ip != ip);
+                if self.ip_history.len() == MAX_IP_HISTORY {
+                    self.ip_history.remove(0);
+                }
+
+                self.ip_history.push(IpBinding {
+                    ip,
+                    first_seen: sighting.wall_time,
+                    last_seen: sighting.wall_time,
+                });
+            }
+        }
+    }
+}
+
+/// Table of recently seen devices, keyed by mac address
 pub struct MacCache {
-    cache: HashMap<MacAddr, Instant>,
+    cache: HashMap<MacAddr, DeviceEntry>,
+}
+
+impl Default for MacCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MacCache {
+    /// Creates an empty cache
     pub fn new() -> Self {
         MacCache {
             cache: HashMap::new(),
         }
     }
 
-    pub fn add(&mut self, mac: MacAddr) {
-        self.cache.insert(mac, Instant::now());
+    /// Records a sighting of mac, creating its entry if needed
+    pub fn record(&mut self, mac: MacAddr, sighting: Sighting) {
+        self.cache
+            .entry(mac)
+            .or_insert_with(|| DeviceEntry::new(&sighting))
+            .record(sighting);
+    }
+
+    /// Attaches announced details to mac, without counting it as a sighting
+    /// Returns false if mac is not cached.
+    pub fn enrich(&mut self, mac: &MacAddr, details: DeviceDetails) -> bool {
+        match self.cache.get_mut(mac) {
+            Some(entry) => {
+                entry.details.merge(details);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the entry of from to mac, unless mac already has one
+    /// The entry of from is removed either way, so the device is not counted twice.
+    pub fn rekey(&mut self, from: &MacAddr, mac: MacAddr) {
+        if let Some(entry) = self.cache.remove(from) {
+            self.cache.entry(mac).or_insert(entry);
+        }
     }
 
+    /// Removes mac from the cache
     pub fn delete(&mut self, mac: &MacAddr) {
         self.cache.remove(mac);
     }
 
-    pub fn iter(&self) -> Iter<MacAddr, Instant> {
+    pub fn get(&self, mac: &MacAddr) -> Option<&DeviceEntry> {
+        self.cache.get(mac)
+    }
+
+    pub fn get_mut(&mut self, mac: &MacAddr) -> Option<&mut DeviceEntry> {
+        self.cache.get_mut(mac)
+    }
+
+    /// Removes every mac last seen more than timeout seconds before now
+    /// Returns the removed macs and their entries
+    pub fn remove_expired(&mut self, now: Instant, timeout: u64) -> Vec<(MacAddr, DeviceEntry)> {
+        let expired: Vec<MacAddr> = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| {
+                now.saturating_duration_since(entry.last_seen_at).as_secs() > timeout
+            })
+            .map(|(mac, _)| *mac)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|mac| self.cache.remove(&mac).map(|entry| (mac, entry)))
+            .collect()
+    }
+
+    /// Iterates over cached mac addresses and their entries
+    pub fn iter(&self) -> Iter<'_, MacAddr, DeviceEntry> {
         self.cache.iter()
     }
 
+    /// Number of cached mac addresses
     pub fn size(&self) -> usize {
         self.cache.keys().len()
     }
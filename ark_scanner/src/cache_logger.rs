@@ -1,4 +1,7 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use log::log;
 use reqwest::{
@@ -7,21 +10,28 @@ use reqwest::{
 };
 use serde::Serialize;
 
-// Generic wrapper for local and API logger
+use crate::error::SinkErr;
+use crate::secret::Secret;
+
+/// Generic wrapper for local and API logger
 pub struct CacheLogger<'a> {
     inner: Box<dyn Logger + 'a>,
 }
 
 impl<'a> Logger for CacheLogger<'a> {
-    fn log(&mut self, location: String, device_count: u64) {
-        self.inner.as_mut().log(location, device_count)
+    fn log(&mut self, location: String, counts: &DeviceCounts) {
+        self.inner.as_mut().log(location, counts)
     }
 }
 
 impl<'a> CacheLogger<'a> {
+    /// Creates an API logger if both url and max_retries are provided,
+    /// otherwise falls back to logging locally.
+    ///
+    /// failure_cb is run after every failed API request.
     pub fn new(
         url: Option<String>,
-        api_key: Option<String>,
+        api_key: Option<Secret>,
         max_retries: Option<u64>,
         failure_cb: impl Fn() + 'a,
     ) -> Self {
@@ -37,8 +47,36 @@ impl<'a> CacheLogger<'a> {
     }
 }
 
+/// Number of counted devices, in total and broken down by class and vendor
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceCounts {
+    pub total: u64,
+    /// Devices with a randomized (locally administered) mac, included in total
+    pub randomized: u64,
+    pub by_class: BTreeMap<String, u64>,
+    /// Devices with an unknown vendor are counted as 'unknown'
+    pub by_vendor: BTreeMap<String, u64>,
+}
+
+impl DeviceCounts {
+    /// Counts a device of class and vendor
+    pub fn add(&mut self, class: impl ToString, vendor: Option<&str>, randomized: bool) {
+        self.total += 1;
+        if randomized {
+            self.randomized += 1;
+        }
+        *self.by_class.entry(class.to_string()).or_default() += 1;
+        *self
+            .by_vendor
+            .entry(String::from(vendor.unwrap_or("unknown")))
+            .or_default() += 1;
+    }
+}
+
+/// Sink for periodic device counts
 pub trait Logger {
-    fn log(&mut self, _location: String, _device_count: u64) {}
+    /// Logs the number of devices seen at location
+    fn log(&mut self, _location: String, _counts: &DeviceCounts) {}
 }
 
 // Logger for APIs
@@ -48,13 +86,13 @@ struct APILogger<'a> {
     url: String,
     retries_exceeded_cb: Box<dyn Fn() + 'a>,
     http_client: Client,
-    api_key: Option<String>,
+    api_key: Option<Secret>,
 }
 
 impl<'a> APILogger<'a> {
     pub fn new(
         url: String,
-        api_key: Option<String>,
+        api_key: Option<Secret>,
         max_retries: u64,
         retries_exceeded_cb: Box<dyn Fn() + 'a>,
     ) -> Self {
@@ -69,19 +107,26 @@ impl<'a> APILogger<'a> {
 }
 
 #[derive(Serialize)]
-struct LogBody {
+struct LogBody<'a> {
     location: String,
     device_count: u64,
+    randomized_device_count: u64,
     created_at: u64,
+    devices_by_class: &'a BTreeMap<String, u64>,
+    devices_by_vendor: &'a BTreeMap<String, u64>,
 }
 
 impl<'a> Logger for APILogger<'a> {
-    fn log(&mut self, location: String, device_count: u64) {
+    fn log(&mut self, location: String, counts: &DeviceCounts) {
         let mut failure_count = 0;
 
+        // counts with a bogus timestamp would be misplaced in the time series
         let epoch_time = match SystemTime::now().duration_since(UNIX_EPOCH) {
             Ok(t) => t.as_secs(),
-            Err(_) => panic!("System time is before UNIX_EPOCH"),
+            Err(e) => {
+                log!(log::Level::Error, "{}", SinkErr::Clock(e));
+                return;
+            }
         };
 
         while failure_count < self.max_retries {
@@ -91,8 +136,11 @@ impl<'a> Logger for APILogger<'a> {
                 &self.api_key,
                 &LogBody {
                     location: location.clone(),
-                    device_count,
+                    device_count: counts.total,
+                    randomized_device_count: counts.randomized,
                     created_at: epoch_time,
+                    devices_by_class: &counts.by_class,
+                    devices_by_vendor: &counts.by_vendor,
                 },
             );
 
@@ -101,18 +149,20 @@ impl<'a> Logger for APILogger<'a> {
                     log!(
                         log::Level::Trace,
                         "successfully logged cache size to api: {}",
-                        device_count
+                        counts.total
                     );
 
                     return;
                 }
-                Err(e) => {
+                Err(error) => {
                     failure_count += 1;
                     log!(
                         log::Level::Error,
-                        "failed attempted to log to: {}, error: {}",
-                        &self.url,
-                        e
+                        "{}",
+                        SinkErr::Api {
+                            url: self.url.clone(),
+                            error
+                        }
                     );
                     self.retries_exceeded_cb.as_ref()();
                 }
@@ -124,7 +174,7 @@ impl<'a> Logger for APILogger<'a> {
 fn send_request<T>(
     client: &Client,
     url: &String,
-    api_key: &Option<String>,
+    api_key: &Option<Secret>,
     body: &T,
 ) -> Result<Response>
 where
@@ -133,7 +183,7 @@ where
     let mut request = client.post(url).json(body);
 
     if let Some(api_key) = api_key {
-        request = request.header("x-api-key", api_key);
+        request = request.header("x-api-key", api_key.expose());
     }
 
     match request.send() {
@@ -146,7 +196,36 @@ where
 struct LocalLogger {}
 
 impl Logger for LocalLogger {
-    fn log(&mut self, _: String, device_count: u64) {
-        log!(log::Level::Info, "mac cache size: {}", device_count)
+    fn log(&mut self, location: String, counts: &DeviceCounts) {
+        log!(
+            log::Level::Info,
+            "mac cache size at {}: {}",
+            location,
+            counts.total
+        );
+
+        if counts.randomized > 0 {
+            log!(
+                log::Level::Debug,
+                "devices with randomized macs at {}: {}",
+                location,
+                counts.randomized
+            );
+        }
+
+        if !counts.by_class.is_empty() {
+            let by_class: Vec<String> = counts
+                .by_class
+                .iter()
+                .map(|(class, count)| format!("{class}: {count}"))
+                .collect();
+
+            log!(
+                log::Level::Debug,
+                "devices at {} by class: {}",
+                location,
+                by_class.join(", ")
+            );
+        }
     }
 }
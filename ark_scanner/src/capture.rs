@@ -0,0 +1,842 @@
+//   File format references:
+// - https://wiki.wireshark.org/Development/LibpcapFileFormat
+// - https://www.ietf.org/archive/id/draft-tuexen-opsawg-pcapng-05.html
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::log;
+use pnet_datalink::NetworkInterface;
+
+use crate::sync::lock;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+const PCAPNG_OPT_END: u16 = 0;
+const PCAPNG_OPT_SHB_USERAPPL: u16 = 4;
+const PCAPNG_OPT_IF_NAME: u16 = 2;
+const PCAPNG_OPT_IF_DESCRIPTION: u16 = 3;
+const PCAPNG_OPT_IF_IPV4ADDR: u16 = 4;
+const PCAPNG_OPT_IF_MACADDR: u16 = 6;
+const PCAPNG_OPT_IF_TSRESOL: u16 = 9;
+
+// timestamps are written in nanoseconds
+const WRITER_TSRESOL: u8 = 9;
+
+const LINKTYPE_ETHERNET: u32 = 1;
+
+// Lengths are read from the file, and are checked before anything is allocated
+// Largest frame read, the largest snaplen of libpcap
+const MAX_FRAME_LEN: usize = 256 * 1024;
+// Largest pcapng block read, a packet block of the largest frame with room for its options
+const MAX_BLOCK_LEN: usize = MAX_FRAME_LEN + 64 * 1024;
+
+/// Ethernet frame read from a capture file
+pub struct CapturedFrame {
+    /// Capture time, since UNIX_EPOCH
+    pub timestamp: Duration,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+struct PcapngInterface {
+    linktype: u32,
+    snaplen: u32,
+    // timestamp units per second
+    ts_resolution: u64,
+}
+
+enum Format {
+    Pcap {
+        order: ByteOrder,
+        nanos: bool,
+        snaplen: u32,
+    },
+    Pcapng {
+        order: ByteOrder,
+        interfaces: Vec<PcapngInterface>,
+        // simple packet blocks carry no timestamp
+        last_timestamp: Duration,
+    },
+}
+
+/// Reader for pcap and pcapng capture files
+///
+/// Only Ethernet frames are returned, frames of other link types are skipped.
+pub struct CaptureReader<R: Read> {
+    input: R,
+    format: Format,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Detects the capture format from the file header
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+
+        let format = if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            Format::Pcapng {
+                order: read_section_header(&mut input)?,
+                interfaces: vec![],
+                last_timestamp: Duration::ZERO,
+            }
+        } else {
+            let (order, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+                (PCAP_MAGIC_MICROS, _) => (ByteOrder::Little, false),
+                (PCAP_MAGIC_NANOS, _) => (ByteOrder::Little, true),
+                (_, PCAP_MAGIC_MICROS) => (ByteOrder::Big, false),
+                (_, PCAP_MAGIC_NANOS) => (ByteOrder::Big, true),
+                _ => return Err(invalid_data("not a pcap or pcapng file")),
+            };
+
+            // version, timezone and sigfigs are not needed
+            let mut header = [0u8; 20];
+            input.read_exact(&mut header)?;
+
+            if order.u32(&header[16..20]) != LINKTYPE_ETHERNET {
+                return Err(invalid_data("capture link type is not ethernet"));
+            }
+
+            Format::Pcap {
+                order,
+                nanos,
+                snaplen: order.u32(&header[12..16]),
+            }
+        };
+
+        Ok(Self { input, format })
+    }
+
+    /// Reads the next Ethernet frame, returns None at the end of the file
+    pub fn next_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+        match self.format {
+            Format::Pcap {
+                order,
+                nanos,
+                snaplen,
+            } => self.next_pcap_frame(order, nanos, snaplen),
+            Format::Pcapng { .. } => self.next_pcapng_frame(),
+        }
+    }
+
+    fn next_pcap_frame(
+        &mut self,
+        order: ByteOrder,
+        nanos: bool,
+        snaplen: u32,
+    ) -> io::Result<Option<CapturedFrame>> {
+        let mut header = [0u8; 16];
+        if !read_exact_or_eof(&mut self.input, &mut header)? {
+            return Ok(None);
+        }
+
+        let secs = order.u32(&header[0..4]) as u64;
+        let fraction = order.u32(&header[4..8]);
+        let captured_len = order.u32(&header[8..12]) as usize;
+        if captured_len > max_frame_len(snaplen) {
+            return Err(invalid_data("pcap frame is larger than the snaplen"));
+        }
+
+        let mut data = vec![0u8; captured_len];
+        self.input.read_exact(&mut data)?;
+
+        let timestamp = if nanos {
+            Duration::new(secs, fraction)
+        } else {
+            Duration::new(secs, fraction.saturating_mul(1000))
+        };
+
+        Ok(Some(CapturedFrame { timestamp, data }))
+    }
+
+    fn next_pcapng_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+        loop {
+            let mut header = [0u8; 8];
+            if !read_exact_or_eof(&mut self.input, &mut header)? {
+                return Ok(None);
+            }
+
+            // a new section may change byte order and resets interfaces
+            if u32::from_le_bytes([header[0], header[1], header[2], header[3]])
+                == PCAPNG_SECTION_HEADER
+            {
+                let new_order = read_section_header_after(&mut self.input, &header[4..8])?;
+                if let Format::Pcapng {
+                    order, interfaces, ..
+                } = &mut self.format
+                {
+                    *order = new_order;
+                    interfaces.clear();
+                }
+                continue;
+            }
+
+            let (order, interfaces, last_timestamp) = match &mut self.format {
+                Format::Pcapng {
+                    order,
+                    interfaces,
+                    last_timestamp,
+                } => (*order, interfaces, last_timestamp),
+                _ => unreachable!(),
+            };
+
+            let block_type = order.u32(&header[0..4]);
+            let total_len = order.u32(&header[4..8]) as usize;
+            if total_len < 12 || total_len % 4 != 0 {
+                return Err(invalid_data("invalid pcapng block length"));
+            }
+            if total_len > MAX_BLOCK_LEN {
+                return Err(invalid_data("pcapng block is too large"));
+            }
+
+            // block body followed by the repeated block length
+            let mut block = vec![0u8; total_len - 8];
+            self.input.read_exact(&mut block)?;
+            let body = &block[..block.len() - 4];
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => {
+                    interfaces.push(parse_interface_description(order, body)?);
+                }
+                PCAPNG_ENHANCED_PACKET | PCAPNG_PACKET => {
+                    if body.len() < 20 {
+                        return Err(invalid_data("truncated pcapng packet block"));
+                    }
+
+                    let interface_id = if block_type == PCAPNG_PACKET {
+                        order.u16(&body[0..2]) as usize
+                    } else {
+                        order.u32(&body[0..4]) as usize
+                    };
+                    let ts =
+                        ((order.u32(&body[4..8]) as u64) << 32) | order.u32(&body[8..12]) as u64;
+                    let captured_len = order.u32(&body[12..16]) as usize;
+
+                    let interface = match interfaces.get(interface_id) {
+                        Some(interface) => interface,
+                        None => return Err(invalid_data("packet references unknown interface")),
+                    };
+
+                    if captured_len > max_frame_len(interface.snaplen) {
+                        return Err(invalid_data("pcapng frame is larger than the snaplen"));
+                    }
+
+                    let data = match body.get(20..20 + captured_len) {
+                        Some(data) => data,
+                        None => return Err(invalid_data("truncated pcapng packet block")),
+                    };
+
+                    *last_timestamp = units_to_duration(ts, interface.ts_resolution);
+
+                    if interface.linktype == LINKTYPE_ETHERNET {
+                        return Ok(Some(CapturedFrame {
+                            timestamp: *last_timestamp,
+                            data: data.to_vec(),
+                        }));
+                    }
+                }
+                PCAPNG_SIMPLE_PACKET => {
+                    let interface = match interfaces.first() {
+                        Some(interface) => interface,
+                        None => return Err(invalid_data("packet references unknown interface")),
+                    };
+
+                    if body.len() < 4 {
+                        return Err(invalid_data("truncated pcapng packet block"));
+                    }
+
+                    let original_len = order.u32(&body[0..4]) as usize;
+                    let captured_len = if interface.snaplen == 0 {
+                        original_len
+                    } else {
+                        original_len.min(interface.snaplen as usize)
+                    };
+
+                    let data = match body.get(4..4 + captured_len) {
+                        Some(data) => data,
+                        None => return Err(invalid_data("truncated pcapng packet block")),
+                    };
+
+                    if interface.linktype == LINKTYPE_ETHERNET {
+                        return Ok(Some(CapturedFrame {
+                            timestamp: *last_timestamp,
+                            data: data.to_vec(),
+                        }));
+                    }
+                }
+                // statistics, name resolution, custom blocks...
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CapturedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+/// Writer of pcapng files that rotates once a file reaches a size limit
+///
+/// The current file is always at path, older files are renamed to path.1, path.2, ...
+/// up to max_files in total, the oldest being deleted.
+///
+/// Every file describes all interfaces, frames refer to them by their index.
+pub struct CaptureWriter {
+    path: PathBuf,
+    interfaces: Vec<NetworkInterface>,
+    rotate_bytes: u64,
+    max_files: usize,
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl CaptureWriter {
+    /// Creates or truncates the file at path, describing interfaces in its header
+    pub fn create(
+        path: &Path,
+        interfaces: &[NetworkInterface],
+        rotate_bytes: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        let mut writer = Self {
+            path: path.to_path_buf(),
+            interfaces: interfaces.to_vec(),
+            rotate_bytes,
+            max_files: max_files.max(1),
+            file: BufWriter::new(File::create(path)?),
+            written: 0,
+        };
+        writer.write_header()?;
+
+        Ok(writer)
+    }
+
+    /// Appends a frame captured on interfaces[interface_id] at timestamp (since UNIX_EPOCH)
+    pub fn write_frame(
+        &mut self,
+        interface_id: u32,
+        timestamp: Duration,
+        data: &[u8],
+    ) -> io::Result<()> {
+        if self.written >= self.rotate_bytes {
+            self.rotate()?;
+        }
+
+        let ts = timestamp.as_nanos() as u64;
+
+        let mut body = vec![];
+        body.extend_from_slice(&interface_id.to_le_bytes());
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pad_to_u32(&mut body);
+
+        self.write_block(PCAPNG_ENHANCED_PACKET, &body)?;
+
+        // captures are evidence, keep them complete if the scanner dies
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+
+        if self.max_files == 1 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files - 1).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+
+        self.write_header()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut section = vec![];
+        section.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes()); // major version
+        section.extend_from_slice(&0u16.to_le_bytes()); // minor version
+        section.extend_from_slice(&(-1i64).to_le_bytes()); // unknown section length
+        push_option(
+            &mut section,
+            PCAPNG_OPT_SHB_USERAPPL,
+            concat!("ark-scanner ", env!("CARGO_PKG_VERSION")).as_bytes(),
+        );
+        push_option(&mut section, PCAPNG_OPT_END, &[]);
+        self.write_block(PCAPNG_SECTION_HEADER, &section)?;
+
+        for interface in self.interfaces.clone() {
+            self.write_interface_description(&interface)?;
+        }
+
+        self.file.flush()
+    }
+
+    fn write_interface_description(&mut self, interface: &NetworkInterface) -> io::Result<()> {
+        let mut description = vec![];
+        description.extend_from_slice(&(LINKTYPE_ETHERNET as u16).to_le_bytes());
+        description.extend_from_slice(&0u16.to_le_bytes()); // reserved
+        description.extend_from_slice(&0u32.to_le_bytes()); // no snaplen
+        push_option(
+            &mut description,
+            PCAPNG_OPT_IF_NAME,
+            interface.name.as_bytes(),
+        );
+        if !interface.description.is_empty() {
+            push_option(
+                &mut description,
+                PCAPNG_OPT_IF_DESCRIPTION,
+                interface.description.as_bytes(),
+            );
+        }
+        for network in &interface.ips {
+            if let (IpAddr::V4(ip), IpAddr::V4(mask)) = (network.ip(), network.mask()) {
+                let mut value = ip.octets().to_vec();
+                value.extend_from_slice(&mask.octets());
+                push_option(&mut description, PCAPNG_OPT_IF_IPV4ADDR, &value);
+            }
+        }
+        if let Some(mac) = interface.mac {
+            push_option(&mut description, PCAPNG_OPT_IF_MACADDR, &mac.octets());
+        }
+        push_option(&mut description, PCAPNG_OPT_IF_TSRESOL, &[WRITER_TSRESOL]);
+        push_option(&mut description, PCAPNG_OPT_END, &[]);
+        self.write_block(PCAPNG_INTERFACE_DESCRIPTION, &description)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let total_len = (body.len() + 12) as u32;
+
+        self.file.write_all(&block_type.to_le_bytes())?;
+        self.file.write_all(&total_len.to_le_bytes())?;
+        self.file.write_all(body)?;
+        self.file.write_all(&total_len.to_le_bytes())?;
+        self.written += total_len as u64;
+
+        Ok(())
+    }
+}
+
+/// Capture writer shared by the sending and receiving threads of the scanner
+pub struct CaptureSink {
+    writer: Mutex<CaptureWriter>,
+}
+
+impl CaptureSink {
+    pub fn new(writer: CaptureWriter) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Writes frame seen on interface_id with the current time,
+    /// failures are logged and the frame dropped
+    pub fn record(&self, interface_id: u32, frame: &[u8]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut writer = lock(&self.writer);
+
+        if let Err(e) = writer.write_frame(interface_id, timestamp, frame) {
+            log!(
+                log::Level::Error,
+                "failed to write frame to {}: {}",
+                writer.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad_to_u32(buf);
+}
+
+fn pad_to_u32(buf: &mut Vec<u8>) {
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+// Reads the rest of a section header block whose type was already consumed
+fn read_section_header<R: Read>(input: &mut R) -> io::Result<ByteOrder> {
+    let mut raw_len = [0u8; 4];
+    input.read_exact(&mut raw_len)?;
+    read_section_header_after(input, &raw_len)
+}
+
+// Reads the rest of a section header block whose type and length were already consumed
+fn read_section_header_after<R: Read>(input: &mut R, raw_len: &[u8]) -> io::Result<ByteOrder> {
+    let mut byte_order_magic = [0u8; 4];
+    input.read_exact(&mut byte_order_magic)?;
+
+    let order = if u32::from_le_bytes(byte_order_magic) == PCAPNG_BYTE_ORDER_MAGIC {
+        ByteOrder::Little
+    } else if u32::from_be_bytes(byte_order_magic) == PCAPNG_BYTE_ORDER_MAGIC {
+        ByteOrder::Big
+    } else {
+        return Err(invalid_data("invalid pcapng byte order magic"));
+    };
+
+    let total_len = order.u32(raw_len) as usize;
+    if total_len < 28 || total_len % 4 != 0 {
+        return Err(invalid_data("invalid pcapng section header length"));
+    }
+
+    // version, section length and options are not needed, and skipped without being buffered
+    let rest = (total_len - 12) as u64;
+    if io::copy(&mut input.take(rest), &mut io::sink())? < rest {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+
+    Ok(order)
+}
+
+fn parse_interface_description(order: ByteOrder, body: &[u8]) -> io::Result<PcapngInterface> {
+    if body.len() < 8 {
+        return Err(invalid_data("truncated pcapng interface description"));
+    }
+
+    let mut interface = PcapngInterface {
+        linktype: order.u16(&body[0..2]) as u32,
+        snaplen: order.u32(&body[4..8]),
+        ts_resolution: 1_000_000,
+    };
+
+    let mut options = &body[8..];
+    while options.len() >= 4 {
+        let code = order.u16(&options[0..2]);
+        let len = order.u16(&options[2..4]) as usize;
+        let padded_len = (len + 3) & !3;
+
+        if code == PCAPNG_OPT_END || options.len() < 4 + padded_len {
+            break;
+        }
+
+        if code == PCAPNG_OPT_IF_TSRESOL && len == 1 {
+            let resolution = options[4];
+            interface.ts_resolution = if resolution & 0x80 == 0 {
+                10u64.saturating_pow(resolution as u32)
+            } else {
+                2u64.saturating_pow((resolution & 0x7f) as u32)
+            };
+        }
+
+        options = &options[4 + padded_len..];
+    }
+
+    Ok(interface)
+}
+
+// Largest frame of an interface, snaplen 0 means unlimited
+fn max_frame_len(snaplen: u32) -> usize {
+    match snaplen as usize {
+        0 => MAX_FRAME_LEN,
+        snaplen => snaplen.min(MAX_FRAME_LEN),
+    }
+}
+
+fn units_to_duration(ts: u64, units_per_sec: u64) -> Duration {
+    let nanos = (ts % units_per_sec) as u128 * 1_000_000_000 / units_per_sec as u128;
+    Duration::new(ts / units_per_sec, nanos as u32)
+}
+
+// Returns false if the input ended before the first byte was read
+fn read_exact_or_eof<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Cursor};
+
+    use pnet_datalink::MacAddr;
+
+    use super::*;
+    use crate::simulation::SimulatedLan;
+
+    fn frames() -> Vec<(Duration, Vec<u8>)> {
+        vec![
+            (Duration::new(1_700_000_000, 123_456_789), vec![0xff; 42]),
+            (Duration::new(1_700_000_001, 0), (0..=255).collect()),
+            // empty frames and frames that need padding
+            (Duration::new(1_700_000_002, 1), vec![]),
+            (Duration::new(1_700_000_003, 999_999_999), vec![1, 2, 3]),
+        ]
+    }
+
+    fn read_all(capture: &[u8]) -> io::Result<Vec<(Duration, Vec<u8>)>> {
+        CaptureReader::new(Cursor::new(capture))?
+            .map(|frame| frame.map(|frame| (frame.timestamp, frame.data)))
+            .collect()
+    }
+
+    /// Capture written by CaptureWriter, frames alternating between two interfaces
+    fn pcapng(test: &str) -> Vec<u8> {
+        let path =
+            env::temp_dir().join(format!("ark-scanner-{}-{test}.pcapng", std::process::id()));
+        let interfaces = [
+            SimulatedLan::interface(
+                "eth0",
+                MacAddr(2, 0, 0, 0, 0, 1),
+                "10.0.0.1/24".parse().unwrap(),
+            ),
+            SimulatedLan::interface(
+                "eth1",
+                MacAddr(2, 0, 0, 0, 0, 2),
+                "10.0.1.1/24".parse().unwrap(),
+            ),
+        ];
+
+        let mut writer = CaptureWriter::create(&path, &interfaces, u64::MAX, 1).unwrap();
+        for (i, (timestamp, data)) in frames().iter().enumerate() {
+            writer.write_frame(i as u32 % 2, *timestamp, data).unwrap();
+        }
+        drop(writer);
+
+        let capture = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        capture
+    }
+
+    /// pcap file of frames, in byte order with micro or nanosecond timestamps
+    fn pcap(
+        order: ByteOrder,
+        nanos: bool,
+        snaplen: u32,
+        frames: &[(Duration, Vec<u8>)],
+    ) -> Vec<u8> {
+        let u16 = |value: u16| match order {
+            ByteOrder::Little => value.to_le_bytes().to_vec(),
+            ByteOrder::Big => value.to_be_bytes().to_vec(),
+        };
+        let u32 = |value: u32| match order {
+            ByteOrder::Little => value.to_le_bytes().to_vec(),
+            ByteOrder::Big => value.to_be_bytes().to_vec(),
+        };
+
+        let magic = if nanos {
+            PCAP_MAGIC_NANOS
+        } else {
+            PCAP_MAGIC_MICROS
+        };
+        let mut capture = [
+            u32(magic),
+            u16(2),
+            u16(4),
+            u32(0),
+            u32(0),
+            u32(snaplen),
+            u32(LINKTYPE_ETHERNET),
+        ]
+        .concat();
+
+        for (timestamp, data) in frames {
+            let fraction = if nanos {
+                timestamp.subsec_nanos()
+            } else {
+                timestamp.subsec_micros()
+            };
+            capture.extend(
+                [
+                    u32(timestamp.as_secs() as u32),
+                    u32(fraction),
+                    u32(data.len() as u32),
+                    u32(data.len() as u32),
+                    data.clone(),
+                ]
+                .concat(),
+            );
+        }
+
+        capture
+    }
+
+    /// Offset of the first packet block in a capture of frames(), after the section header and
+    /// interface descriptions
+    fn first_packet(capture: &[u8]) -> usize {
+        let packets: usize = frames()
+            .iter()
+            .map(|(_, data)| 32 + (data.len() + 3) / 4 * 4)
+            .sum();
+        let packet = capture.len() - packets;
+
+        assert_eq!(
+            u32::from_le_bytes(capture[packet..packet + 4].try_into().unwrap()),
+            PCAPNG_ENHANCED_PACKET
+        );
+        packet
+    }
+
+    fn is_invalid_data(result: io::Result<Vec<(Duration, Vec<u8>)>>) -> bool {
+        matches!(result, Err(e) if e.kind() == io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn pcapng_round_trips() {
+        assert_eq!(read_all(&pcapng("round-trip")).unwrap(), frames());
+    }
+
+    #[test]
+    fn pcap_is_read_in_both_byte_orders() {
+        let frames = frames();
+
+        let nanos = pcap(ByteOrder::Big, true, 65535, &frames);
+        assert_eq!(read_all(&nanos).unwrap(), frames);
+
+        let micros = pcap(ByteOrder::Little, false, 0, &frames);
+        let truncated: Vec<(Duration, Vec<u8>)> = frames
+            .into_iter()
+            .map(|(timestamp, data)| (Duration::from_micros(timestamp.as_micros() as u64), data))
+            .collect();
+        assert_eq!(read_all(&micros).unwrap(), truncated);
+    }
+
+    #[test]
+    fn truncated_captures_fail() {
+        let packets = frames().len();
+
+        // cuts after the section header, each interface description or a packet read cleanly
+        for (capture, records) in [
+            (pcapng("truncated"), 3 + packets - 1),
+            (pcap(ByteOrder::Little, true, 0, &frames()), 1 + packets - 1),
+        ] {
+            let clean_cuts = (0..capture.len())
+                .filter(|len| read_all(&capture[..*len]).is_ok())
+                .count();
+
+            // any other cut fails instead of panicking
+            assert_eq!(clean_cuts, records);
+            assert!(read_all(&capture).is_ok());
+        }
+    }
+
+    #[test]
+    fn oversized_frames_fail_before_allocating() {
+        let frame = vec![(Duration::ZERO, vec![0; 128])];
+
+        // larger than the snaplen
+        assert!(is_invalid_data(read_all(&pcap(
+            ByteOrder::Little,
+            false,
+            64,
+            &frame
+        ))));
+
+        // length of 4GiB, the frame itself is missing
+        let mut huge = pcap(ByteOrder::Little, false, 0, &frame);
+        huge[24 + 8..24 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(is_invalid_data(read_all(&huge)));
+
+        let mut huge_block = pcapng("huge-block");
+        let packet = first_packet(&huge_block);
+        huge_block[packet + 4..packet + 8].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        assert!(is_invalid_data(read_all(&huge_block)));
+    }
+
+    #[test]
+    fn corrupt_captures_fail() {
+        assert!(is_invalid_data(read_all(b"not a capture file at all")));
+
+        let mut not_ethernet = pcap(ByteOrder::Little, false, 0, &frames());
+        not_ethernet[20] = 101;
+        assert!(is_invalid_data(read_all(&not_ethernet)));
+
+        let valid = pcapng("corrupt");
+        let packet = first_packet(&valid);
+
+        let mut misaligned = valid.clone();
+        misaligned[packet + 4] += 1;
+        assert!(is_invalid_data(read_all(&misaligned)));
+
+        let mut unknown_interface = valid.clone();
+        unknown_interface[packet + 8] = 7;
+        assert!(is_invalid_data(read_all(&unknown_interface)));
+
+        // captured length past the end of its block
+        let mut overlong = valid.clone();
+        overlong[packet + 20] = 200;
+        assert!(is_invalid_data(read_all(&overlong)));
+
+        let mut byte_order = valid;
+        byte_order[8] = 0;
+        assert!(is_invalid_data(read_all(&byte_order)));
+    }
+}
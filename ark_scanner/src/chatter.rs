@@ -0,0 +1,145 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+use pnet::packet::{
+    ethernet::{EtherTypes, EthernetPacket},
+    ip::IpNextHeaderProtocols,
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    udp::UdpPacket,
+    Packet,
+};
+use pnet_datalink::MacAddr;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+pub const MDNS_PORT: u16 = 5353;
+
+/// UDP datagram parsed from an Ethernet frame
+pub struct UdpFrame {
+    /// Source mac of the Ethernet frame
+    pub eth_source: MacAddr,
+    pub source_ip: IpAddr,
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub payload: Vec<u8>,
+}
+
+impl UdpFrame {
+    /// Parses a UDP over IPv4 or IPv6 frame, returns None for any other frame
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let eth_packet = EthernetPacket::new(frame)?;
+        let ethertype = eth_packet.get_ethertype();
+
+        let (source_ip, segment) = if ethertype == EtherTypes::Ipv4 {
+            let ip_packet = Ipv4Packet::new(eth_packet.payload())?;
+            if ip_packet.get_next_level_protocol() != IpNextHeaderProtocols::Udp {
+                return None;
+            }
+            (
+                IpAddr::V4(ip_packet.get_source()),
+                ip_packet.payload().to_vec(),
+            )
+        } else if ethertype == EtherTypes::Ipv6 {
+            let ip_packet = Ipv6Packet::new(eth_packet.payload())?;
+            if ip_packet.get_next_header() != IpNextHeaderProtocols::Udp {
+                return None;
+            }
+            (
+                IpAddr::V6(ip_packet.get_source()),
+                ip_packet.payload().to_vec(),
+            )
+        } else {
+            return None;
+        };
+
+        let udp_packet = UdpPacket::new(&segment)?;
+
+        Some(Self {
+            eth_source: eth_packet.get_source(),
+            source_ip,
+            source_port: udp_packet.get_source(),
+            destination_port: udp_packet.get_destination(),
+            payload: udp_packet.payload().to_vec(),
+        })
+    }
+}
+
+/// Protocol of broadcast or multicast chatter sent by a device on its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatterKind {
+    /// DHCP client message
+    Dhcp,
+    /// mDNS query or announcement
+    Mdns,
+}
+
+impl Display for ChatterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatterKind::Dhcp => write!(f, "dhcp"),
+            ChatterKind::Mdns => write!(f, "mdns"),
+        }
+    }
+}
+
+/// Chatter besides ARP and NDP that counts as a device being present
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PassiveSources {
+    pub dhcp: bool,
+    pub mdns: bool,
+}
+
+impl PassiveSources {
+    pub fn is_empty(&self) -> bool {
+        !self.dhcp && !self.mdns
+    }
+
+    /// Returns the kind of chatter in datagram, if it is an enabled source
+    pub fn classify(&self, datagram: &UdpFrame) -> Option<ChatterKind> {
+        if self.dhcp
+            && datagram.source_port == DHCP_CLIENT_PORT
+            && datagram.destination_port == DHCP_SERVER_PORT
+        {
+            return Some(ChatterKind::Dhcp);
+        }
+
+        if self.mdns && datagram.destination_port == MDNS_PORT {
+            return Some(ChatterKind::Mdns);
+        }
+
+        None
+    }
+}
+
+impl FromStr for PassiveSources {
+    type Err = String;
+
+    /// Parses a comma separated list of dhcp and mdns
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sources = Self::default();
+
+        for source in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match source {
+                "dhcp" => sources.dhcp = true,
+                "mdns" => sources.mdns = true,
+                _ => return Err(format!("unknown passive source: {source}")),
+            }
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Display for PassiveSources {
+    /// Formats the sources as parsed, e.g. dhcp,mdns
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sources = [(self.dhcp, "dhcp"), (self.mdns, "mdns")];
+
+        let enabled: Vec<&str> = sources
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, s)| *s)
+            .collect();
+        write!(f, "{}", enabled.join(","))
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    time::Duration,
+};
+
+use ipnetwork::Ipv4Network;
+use pnet_datalink::NetworkInterface;
+
+use crate::config::{ScanMode, ScannerOptions};
+use crate::error::{ArpScannerErr, ConfigErr, InterfaceErr};
+use crate::network::select_default_interface;
+use crate::oui::OuiDatabase;
+use crate::reprobe::max_retries;
+use crate::scanner::select_interface;
+use crate::targets::TargetSet;
+
+/// How serious a configuration problem is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The scanner runs, but likely not as intended
+    Warning,
+    /// The scanner fails to start, or silently ignores part of the configuration
+    Error,
+}
+
+/// Problem found when checking scanner options
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// Interface the scanner would scan, resolved without opening a channel on it
+pub struct ResolvedInterface {
+    pub name: String,
+    pub location: String,
+    /// IPv4 subnet of the interface, None for IPv6-only interfaces scanned through NDP
+    pub subnet: Option<Ipv4Network>,
+    /// Addresses ARP requests are sent to, in ascending order
+    pub targets: Vec<Ipv4Addr>,
+}
+
+/// Outcome of checking scanner options
+pub struct ConfigReport {
+    pub interfaces: Vec<ResolvedInterface>,
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl ConfigReport {
+    pub fn has_errors(&self) -> bool {
+        self.problems
+            .iter()
+            .any(|problem| problem.severity == Severity::Error)
+    }
+}
+
+/// Checks options and resolves the interfaces and targets they select among interfaces
+///
+/// No channel is opened, so options can be checked without root privileges.
+pub fn check_options(options: &ScannerOptions, interfaces: &[NetworkInterface]) -> ConfigReport {
+    let mut problems = validate(options);
+    let interfaces = resolve_interfaces(options, interfaces, &mut problems);
+
+    ConfigReport {
+        interfaces,
+        problems,
+    }
+}
+
+/// Checks invariants between options that each parsed fine on their own
+pub fn validate(options: &ScannerOptions) -> Vec<ConfigProblem> {
+    let mut problems = vec![];
+
+    for (key, value) in [
+        ("timing.arp_scan_period_secs", options.arp_scan_period),
+        (
+            "timing.mac_cache_log_period_secs",
+            options.mac_cache_log_period,
+        ),
+        ("timing.mac_addr_timeout_secs", options.mac_addr_timeout),
+        ("timing.reprobe_interval_secs", options.reprobe_interval),
+        ("timing.burst_size", u64::from(options.burst_size)),
+        ("timing.max_pps", options.max_pps.map_or(1, u64::from)),
+        (
+            "timing.unknown_sweep_period_secs",
+            options.unknown_sweep_period.unwrap_or(1),
+        ),
+    ] {
+        if value == 0 {
+            problems.push(ConfigProblem::error(format!(
+                "{key} must be greater than 0"
+            )));
+        }
+    }
+
+    if options.mode == ScanMode::Active && options.mac_addr_timeout < options.arp_scan_period {
+        problems.push(ConfigProblem::error(format!(
+            "timing.mac_addr_timeout_secs ({}) is smaller than timing.arp_scan_period_secs ({}), \
+             devices expire before they are probed again",
+            options.mac_addr_timeout, options.arp_scan_period
+        )));
+    }
+
+    let reprobe_time = u64::from(options.reprobe_retries) * options.reprobe_interval;
+    if options.mode == ScanMode::Active
+        && options.reprobe_retries > 0
+        && reprobe_time >= options.mac_addr_timeout
+    {
+        problems.push(ConfigProblem::warning(format!(
+            "timing.reprobe_retries * timing.reprobe_interval_secs ({}) is not smaller than \
+             timing.mac_addr_timeout_secs ({}), only {} re-probes are sent",
+            reprobe_time,
+            options.mac_addr_timeout,
+            max_retries(
+                Duration::from_secs(options.reprobe_interval),
+                Duration::from_secs(options.mac_addr_timeout)
+            )
+        )));
+    }
+
+    // the API logger needs both, anything less silently falls back to logging locally
+    match (&options.log_api_url, options.api_retry_limit) {
+        (Some(_), None) => problems.push(ConfigProblem::error(String::from(
+            "sinks.api_url is set without sinks.api_retry_limit, counts would only be logged \
+             locally",
+        ))),
+        (None, Some(_)) => problems.push(ConfigProblem::error(String::from(
+            "sinks.api_retry_limit is set without sinks.api_url, counts would only be logged \
+             locally",
+        ))),
+        _ => {}
+    }
+
+    if options.log_api_key.is_some() && options.log_api_url.is_none() {
+        problems.push(ConfigProblem::error(String::from(
+            "sinks.api_key is set without sinks.api_url",
+        )));
+    }
+
+    if let Some(url) = &options.log_api_url {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            problems.push(ConfigProblem::error(format!(
+                "sinks.api_url {url} is not an http or https url"
+            )));
+        }
+    }
+
+    if options.privacy && options.capture.is_some() {
+        problems.push(ConfigProblem::error(String::from(
+            "sinks.capture_path cannot be set in privacy mode, capture files hold raw mac addresses",
+        )));
+    }
+
+    if !options.privacy && options.privacy_key.is_some() {
+        problems.push(ConfigProblem::warning(String::from(
+            "privacy.key is set but privacy mode is disabled, the key is not used",
+        )));
+    }
+
+    if let Some(capture) = &options.capture {
+        if capture.max_files == 0 {
+            problems.push(ConfigProblem::error(String::from(
+                "sinks.capture_max_files must be greater than 0",
+            )));
+        }
+    }
+
+    if let Some(path) = &options.oui_db_path {
+        if let Err(e) = OuiDatabase::load(Path::new(path)) {
+            problems.push(ConfigProblem::error(format!(
+                "devices.oui_db_path {path} cannot be read: {e}"
+            )));
+        }
+    }
+
+    problems
+}
+
+/// Fails with the errors [`validate`] finds, options with only warnings are accepted
+pub fn ensure_valid(options: &ScannerOptions) -> Result<(), ConfigErr> {
+    let problems: Vec<String> = validate(options)
+        .into_iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .map(|problem| problem.message)
+        .collect();
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigErr::Rejected { problems })
+    }
+}
+
+// Selects interfaces like the scanner does, and the targets within their subnets
+fn resolve_interfaces(
+    options: &ScannerOptions,
+    interfaces: &[NetworkInterface],
+    problems: &mut Vec<ConfigProblem>,
+) -> Vec<ResolvedInterface> {
+    let mut selected = vec![];
+
+    if options.interfaces.is_empty() {
+        match select_default_interface(interfaces) {
+            Some(interface) => selected.push((interface, options.location.clone())),
+            None => problems.push(ConfigProblem::error(String::from(
+                "no interface is configured and no usable default interface was found",
+            ))),
+        }
+    }
+
+    for configured in &options.interfaces {
+        let location = configured
+            .location
+            .clone()
+            .unwrap_or_else(|| options.location.clone());
+
+        match select_interface(interfaces, &configured.selector, options.ndp) {
+            Ok(interface) => selected.push((interface, location)),
+            Err(ArpScannerErr::InterfaceRejected { interface, reason })
+                if interface == configured.selector.to_string() =>
+            {
+                problems.push(ConfigProblem::error(format!(
+                    "interface {interface} {reason}"
+                )))
+            }
+            Err(ArpScannerErr::InterfaceRejected { interface, reason }) => {
+                problems.push(ConfigProblem::error(format!(
+                    "interface {} matching {} {}",
+                    interface, configured.selector, reason
+                )))
+            }
+            Err(e) => problems.push(ConfigProblem::error(e.to_string())),
+        }
+    }
+
+    let mut names = HashSet::new();
+    let mut resolved = vec![];
+
+    for (interface, location) in selected {
+        if !names.insert(interface.name.clone()) {
+            problems.push(ConfigProblem::error(format!(
+                "interface {} is selected more than once",
+                interface.name
+            )));
+            continue;
+        }
+
+        let subnet = match ipv4_subnet(&interface) {
+            Ok(subnet) => subnet,
+            Err(reason) => {
+                problems.push(ConfigProblem::error(format!(
+                    "interface {} {}",
+                    interface.name, reason
+                )));
+                continue;
+            }
+        };
+
+        let targets = subnet.map_or_else(Vec::new, |subnet| options.targets.ips_within(subnet));
+
+        if let Some(subnet) = subnet {
+            if targets.is_empty() {
+                problems.push(ConfigProblem::error(format!(
+                    "no address in subnet {} of interface {} is targeted, \
+                     check targets.include and targets.exclude",
+                    subnet, interface.name
+                )));
+            }
+        }
+
+        resolved.push(ResolvedInterface {
+            name: interface.name,
+            location,
+            subnet,
+            targets,
+        });
+    }
+
+    let subnets: Vec<Ipv4Network> = resolved.iter().filter_map(|i| i.subnet).collect();
+    for range in &options.targets.include {
+        let single = TargetSet::new(vec![*range], vec![]);
+
+        if !subnets.is_empty()
+            && subnets
+                .iter()
+                .all(|subnet| single.ips_within(*subnet).is_empty())
+        {
+            problems.push(ConfigProblem::warning(format!(
+                "targets.include {range} is outside of every scanned subnet"
+            )));
+        }
+    }
+
+    resolved
+}
+
+// First IPv4 subnet of interface, as the scanner picks it
+fn ipv4_subnet(interface: &NetworkInterface) -> Result<Option<Ipv4Network>, InterfaceErr> {
+    let ipv4 = interface
+        .ips
+        .iter()
+        .find_map(|network| match (network.ip(), network.mask()) {
+            (IpAddr::V4(ip), IpAddr::V4(mask)) => Some((ip, mask)),
+            _ => None,
+        });
+
+    match ipv4 {
+        Some((ip, mask)) => Ipv4Network::with_netmask(ip, mask)
+            .map(Some)
+            .map_err(|_| InterfaceErr::InvalidMask),
+        None => Ok(None),
+    }
+}
+
+/// Options as they are used, keyed like the config file, with secrets redacted
+pub fn effective_options(options: &ScannerOptions) -> Vec<(&'static str, String)> {
+    let interfaces: Vec<String> = options
+        .interfaces
+        .iter()
+        .map(|interface| match &interface.location {
+            Some(location) => format!("{}:{}", interface.selector, location),
+            None => interface.selector.to_string(),
+        })
+        .collect();
+
+    let capture = options.capture.as_ref();
+
+    vec![
+        ("mode", options.mode.to_string()),
+        ("location", options.location.clone()),
+        ("trace", options.trace.to_string()),
+        ("reconnect_cmd", options.reconnect_cmd.clone()),
+        ("interfaces", list(&interfaces)),
+        (
+            "timing.mac_addr_timeout_secs",
+            options.mac_addr_timeout.to_string(),
+        ),
+        (
+            "timing.arp_scan_period_secs",
+            options.arp_scan_period.to_string(),
+        ),
+        (
+            "timing.mac_cache_log_period_secs",
+            options.mac_cache_log_period.to_string(),
+        ),
+        ("timing.max_pps", optional(options.max_pps)),
+        ("timing.burst_size", options.burst_size.to_string()),
+        (
+            "timing.randomize_targets",
+            options.randomize_targets.to_string(),
+        ),
+        (
+            "timing.unknown_sweep_period_secs",
+            optional(options.unknown_sweep_period),
+        ),
+        (
+            "timing.reprobe_retries",
+            options.reprobe_retries.to_string(),
+        ),
+        (
+            "timing.reprobe_interval_secs",
+            options.reprobe_interval.to_string(),
+        ),
+        ("targets.include", list(&options.targets.include)),
+        ("targets.exclude", list(&options.targets.exclude)),
+        ("sinks.api_url", optional(options.log_api_url.as_ref())),
+        ("sinks.api_key", optional(options.log_api_key.as_ref())),
+        (
+            "sinks.api_key_file",
+            optional(options.log_api_key_file.as_ref()),
+        ),
+        ("sinks.api_retry_limit", optional(options.api_retry_limit)),
+        (
+            "sinks.capture_path",
+            optional(capture.map(|capture| &capture.path)),
+        ),
+        (
+            "sinks.capture_rotate_bytes",
+            optional(capture.map(|capture| capture.rotate_bytes)),
+        ),
+        (
+            "sinks.capture_max_files",
+            optional(capture.map(|capture| capture.max_files)),
+        ),
+        (
+            "detection.presence_policy",
+            or_none(options.arp_presence_policy.to_string()),
+        ),
+        (
+            "detection.passive_sources",
+            or_none(options.passive_sources.to_string()),
+        ),
+        (
+            "detection.announcement_sources",
+            or_none(options.announcement_sources.to_string()),
+        ),
+        ("detection.ndp", options.ndp.to_string()),
+        (
+            "devices.oui_db_path",
+            optional(options.oui_db_path.as_ref()),
+        ),
+        ("devices.class_rules", list(&options.class_rules)),
+        (
+            "devices.randomized_dedup_window_secs",
+            optional(options.randomized_dedup_window),
+        ),
+        (
+            "devices.exclude_service_types",
+            list(&options.exclusions.service_types),
+        ),
+        (
+            "devices.exclude_models",
+            list(&options.exclusions.model_patterns),
+        ),
+        (
+            "devices.exclude_vendors",
+            list(&options.exclusions.vendor_patterns),
+        ),
+        ("devices.exclude_classes", list(&options.exclusions.classes)),
+        ("privacy.enabled", options.privacy.to_string()),
+        ("privacy.key", optional(options.privacy_key.as_ref())),
+    ]
+}
+
+fn optional(value: Option<impl Display>) -> String {
+    value.map_or_else(|| String::from("not set"), |value| value.to_string())
+}
+
+fn list(values: &[impl Display]) -> String {
+    let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+    or_none(values.join(","))
+}
+
+fn or_none(value: String) -> String {
+    if value.is_empty() {
+        String::from("none")
+    } else {
+        value
+    }
+}
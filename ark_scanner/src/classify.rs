@@ -0,0 +1,330 @@
+use std::{fmt::Display, io, path::Path, str::FromStr};
+
+use log::log;
+use pnet_datalink::MacAddr;
+
+use crate::cache::DeviceEntry;
+use crate::oui::OuiDatabase;
+
+// Applied after the configured rules, the first matching rule wins
+const DEFAULT_RULES: [&str; 28] = [
+    "vendor_class:android=phone",
+    "hostname:iphone=phone",
+    "hostname:android=phone",
+    "hostname:galaxy=phone",
+    "hostname:pixel=phone",
+    "vendor_class:msft=laptop",
+    "hostname:macbook=laptop",
+    "hostname:laptop=laptop",
+    "hostname:desktop=laptop",
+    "vendor:cisco=network",
+    "vendor:aruba=network",
+    "vendor:juniper=network",
+    "vendor:fortinet=network",
+    "vendor:palo alto=network",
+    "vendor:ubiquiti=network",
+    "vendor:routerboard=network",
+    "vendor:netgear=network",
+    "vendor:tp-link=network",
+    "vendor:espressif=iot",
+    "vendor:raspberry pi=iot",
+    "vendor:philips lighting=iot",
+    "vendor:nest labs=iot",
+    "vendor:sonos=iot",
+    "vendor:roku=iot",
+    "vendor:amazon=iot",
+    "service:_googlecast._tcp=iot",
+    "service:_hap._tcp=iot",
+    "service:_ipp._tcp=iot",
+];
+
+/// Kind of device, used to tell people's devices from infrastructure
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DeviceClass {
+    Phone,
+    Laptop,
+    /// Routers, switches, access points and firewalls
+    Network,
+    /// Smart home devices, media players, printers
+    Iot,
+    #[default]
+    Unknown,
+}
+
+impl Display for DeviceClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceClass::Phone => write!(f, "phone"),
+            DeviceClass::Laptop => write!(f, "laptop"),
+            DeviceClass::Network => write!(f, "network"),
+            DeviceClass::Iot => write!(f, "iot"),
+            DeviceClass::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl FromStr for DeviceClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "phone" => Ok(DeviceClass::Phone),
+            "laptop" => Ok(DeviceClass::Laptop),
+            "network" => Ok(DeviceClass::Network),
+            "iot" => Ok(DeviceClass::Iot),
+            "unknown" => Ok(DeviceClass::Unknown),
+            class => Err(format!("unknown device class: {class}")),
+        }
+    }
+}
+
+/// What a class rule matches against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleField {
+    /// Vendor of the mac address
+    Vendor,
+    /// Host name from DHCP or mDNS
+    Hostname,
+    /// DHCP vendor class identifier
+    VendorClass,
+    /// Model from mDNS or SSDP
+    Model,
+    /// Offered service type, matched exactly
+    Service,
+}
+
+/// Rule classifying devices whose field contains pattern (case insensitive)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClassRule {
+    pub field: RuleField,
+    pub pattern: String,
+    pub class: DeviceClass,
+}
+
+impl ClassRule {
+    pub fn matches(&self, entry: &DeviceEntry) -> bool {
+        let details = &entry.details;
+
+        let value = match self.field {
+            RuleField::Vendor => entry.vendor.as_deref(),
+            RuleField::Hostname => details.hostname.as_deref(),
+            RuleField::VendorClass => details.vendor_class.as_deref(),
+            RuleField::Model => details.model.as_deref(),
+            RuleField::Service => return details.services.contains(&self.pattern),
+        };
+
+        value.map_or(false, |value| value.to_lowercase().contains(&self.pattern))
+    }
+}
+
+impl Display for ClassRule {
+    /// Formats the rule as parsed, e.g. vendor:espressif=iot
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let field = match self.field {
+            RuleField::Vendor => "vendor",
+            RuleField::Hostname => "hostname",
+            RuleField::VendorClass => "vendor_class",
+            RuleField::Model => "model",
+            RuleField::Service => "service",
+        };
+
+        write!(f, "{}:{}={}", field, self.pattern, self.class)
+    }
+}
+
+impl FromStr for ClassRule {
+    type Err = String;
+
+    /// Parses field:pattern=class, e.g. vendor:espressif=iot
+    /// Fields are vendor, hostname, vendor_class, model and service.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (condition, class) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("class rule {s} is missing =class"))?;
+        let (field, pattern) = condition
+            .split_once(':')
+            .ok_or_else(|| format!("class rule {s} is missing field:"))?;
+
+        let field = match field.trim() {
+            "vendor" => RuleField::Vendor,
+            "hostname" => RuleField::Hostname,
+            "vendor_class" => RuleField::VendorClass,
+            "model" => RuleField::Model,
+            "service" => RuleField::Service,
+            field => return Err(format!("unknown class rule field {field} in {s}")),
+        };
+
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err(format!("class rule {s} has an empty pattern"));
+        }
+
+        Ok(Self {
+            field,
+            // service types are matched as announced
+            pattern: match field {
+                RuleField::Service => String::from(pattern),
+                _ => pattern.to_lowercase(),
+            },
+            class: class.parse()?,
+        })
+    }
+}
+
+/// Resolves the vendor of cached devices and classifies them
+pub struct DeviceClassifier {
+    oui: OuiDatabase,
+    rules: Vec<ClassRule>,
+}
+
+impl DeviceClassifier {
+    /// Creates a classifier applying rules before the default ones
+    pub fn new(oui: OuiDatabase, rules: Vec<ClassRule>) -> Self {
+        let defaults = DEFAULT_RULES.iter().map(|rule| {
+            rule.parse::<ClassRule>()
+                .unwrap_or_else(|e| panic!("default class rule {rule} is invalid: {e}"))
+        });
+
+        Self {
+            oui,
+            rules: rules.into_iter().chain(defaults).collect(),
+        }
+    }
+
+    /// Creates a classifier with the OUI database at oui_path, or the embedded one if None
+    pub fn load(oui_path: Option<&str>, rules: Vec<ClassRule>) -> io::Result<Self> {
+        let oui = match oui_path {
+            Some(path) => {
+                let oui = OuiDatabase::load(Path::new(path))?;
+                log!(
+                    log::Level::Info,
+                    "loaded {} oui vendors from {}",
+                    oui.len(),
+                    path
+                );
+                oui
+            }
+            None => OuiDatabase::embedded(),
+        };
+
+        Ok(Self::new(oui, rules))
+    }
+
+    /// Updates the vendor and class of the entry of mac
+    pub fn describe(&self, mac: &MacAddr, entry: &mut DeviceEntry) {
+        if entry.vendor.is_none() {
+            entry.vendor = self.oui.lookup(mac).map(String::from);
+        }
+
+        entry.class = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(entry))
+            .map_or(DeviceClass::Unknown, |rule| rule.class);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{DeviceDetails, MacCache, Sighting};
+
+    const MAC: MacAddr = MacAddr(0xb8, 0x27, 0xeb, 0x00, 0x00, 0x01);
+
+    fn entry(details: DeviceDetails) -> DeviceEntry {
+        let mut cache = MacCache::new();
+        cache.record(MAC, Sighting::now(None, "eth0").with_details(details));
+        cache.get(&MAC).unwrap().clone()
+    }
+
+    fn named(hostname: &str) -> DeviceEntry {
+        entry(DeviceDetails {
+            hostname: Some(String::from(hostname)),
+            ..DeviceDetails::default()
+        })
+    }
+
+    fn classify(classifier: &DeviceClassifier, entry: &mut DeviceEntry) -> DeviceClass {
+        classifier.describe(&MAC, entry);
+        entry.class
+    }
+
+    #[test]
+    fn rule_is_parsed_and_formatted() {
+        let rule: ClassRule = " hostname : Living Room =iot".parse().unwrap();
+        assert_eq!(rule.field, RuleField::Hostname);
+        assert_eq!(rule.pattern, "living room");
+        assert_eq!(rule.class, DeviceClass::Iot);
+        assert_eq!(rule.to_string(), "hostname:living room=iot");
+
+        // only the last = separates the class
+        let rule: ClassRule = "model:a=b=phone".parse().unwrap();
+        assert_eq!(rule.pattern, "a=b");
+        assert_eq!(rule.class, DeviceClass::Phone);
+
+        // service types keep their case
+        let rule: ClassRule = "service:_Hap._tcp=iot".parse().unwrap();
+        assert_eq!(rule.pattern, "_Hap._tcp");
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for rule in [
+            "vendor:espressif",
+            "espressif=iot",
+            "serial:123=iot",
+            "vendor: =iot",
+            "vendor:espressif=toaster",
+            "vendor:a=b",
+        ] {
+            assert!(rule.parse::<ClassRule>().is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn default_rules_are_valid() {
+        for rule in DEFAULT_RULES {
+            assert!(rule.parse::<ClassRule>().is_ok(), "{rule}");
+        }
+
+        let classifier = DeviceClassifier::new(OuiDatabase::parse(""), vec![]);
+        assert_eq!(classifier.rules.len(), DEFAULT_RULES.len());
+    }
+
+    #[test]
+    fn patterns_match_case_insensitively_and_services_exactly() {
+        let rule: ClassRule = "hostname:iPhone=phone".parse().unwrap();
+        assert!(rule.matches(&named("Johns-IPHONE-2")));
+        assert!(!rule.matches(&named("ipad")));
+        assert!(!rule.matches(&entry(DeviceDetails::default())));
+
+        let mut details = DeviceDetails::default();
+        details.add_service(Some(String::from("_ipp._tcp")));
+        let printer = entry(details);
+
+        let rule: ClassRule = "service:_ipp._tcp=iot".parse().unwrap();
+        assert!(rule.matches(&printer));
+        for pattern in ["_ipp", "_IPP._tcp"] {
+            let rule: ClassRule = format!("service:{pattern}=iot").parse().unwrap();
+            assert!(!rule.matches(&printer), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn configured_rules_take_precedence_over_defaults() {
+        let oui = OuiDatabase::parse("MA-L,B827EB,Raspberry Pi Foundation,");
+
+        let classifier = DeviceClassifier::new(oui, vec![]);
+        let mut device = named("kiosk");
+        assert_eq!(classify(&classifier, &mut device), DeviceClass::Iot);
+        assert_eq!(device.vendor.as_deref(), Some("Raspberry Pi Foundation"));
+
+        let rules = vec!["hostname:kiosk=laptop".parse().unwrap()];
+        let classifier = DeviceClassifier::new(OuiDatabase::parse(""), rules);
+        assert_eq!(classify(&classifier, &mut device), DeviceClass::Laptop);
+        assert_eq!(
+            classify(&classifier, &mut named("unnamed")),
+            DeviceClass::Unknown
+        );
+    }
+}
@@ -1,10 +1,62 @@
-use std::{fmt::Debug, str::FromStr};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use toml::{value::Table, Value};
+
+use crate::announce::{AnnouncementSources, ExclusionRules};
+use crate::chatter::PassiveSources;
+use crate::classify::ClassRule;
+use crate::error::ConfigErr;
+use crate::network::InterfaceSelector;
+use crate::presence::PresencePolicy;
+use crate::secret::Secret;
+use crate::targets::TargetSet;
+
+const DEFAULT_CAPTURE_ROTATE_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_CAPTURE_MAX_FILES: usize = 5;
+const DEFAULT_BURST_SIZE: u32 = 1;
+const DEFAULT_REPROBE_RETRIES: u32 = 3;
+const DEFAULT_REPROBE_INTERVAL_SECS: u64 = 5;
+
+/// systemd credential the API key is read from, e.g. set with LoadCredential=log_api_key:PATH
+pub const API_KEY_CREDENTIAL: &str = "log_api_key";
+
+/// Runtime options of the scanner
+#[derive(Clone)]
 pub struct ScannerOptions {
+    /// Whether requests are sent or devices are only listened for
+    /// Optional, defaults to 'active'
+    pub mode: ScanMode,
     /// Time until mac address is considered expired, in seconds
     pub mac_addr_timeout: u64,
     /// Interval that ARP requests are sent, in seconds
+    /// Requests are spread evenly over the interval
     pub arp_scan_period: u64,
+    /// Max number of requests sent per second on each interface
+    /// Optional, uncapped by default
+    pub max_pps: Option<u32>,
+    /// Number of requests that may be sent back to back
+    /// Optional, defaults to 1
+    pub burst_size: u32,
+    /// Whether target order is shuffled every scan period
+    /// Optional, defaults to true
+    pub randomize_targets: bool,
+    /// Interval at which addresses no known device uses are swept, in seconds
+    /// Optional, defaults to arp_scan_period
+    pub unknown_sweep_period: Option<u64>,
+    /// Number of times a known device is probed before it expires, 0 sweeps known devices
+    /// like unknown addresses
+    /// Optional, defaults to 3
+    pub reprobe_retries: u32,
+    /// Interval between re-probes of a known device, in seconds
+    /// Optional, defaults to 5
+    pub reprobe_interval: u64,
     /// Interval at which mac cache size is logged, in seconds
     pub mac_cache_log_period: u64,
     /// Whether to log 'trace' level information
@@ -12,51 +64,954 @@ pub struct ScannerOptions {
     /// Command or script to force network reconnect
     pub reconnect_cmd: String,
     /// URL to send log request to
-    /// Optional in .env file
+    /// Optional
     pub log_api_url: Option<String>,
     /// Max number of retries on API failure
-    /// Optional in env file
+    /// Optional
     pub api_retry_limit: Option<u64>,
-    /// API key
-    pub log_api_key: Option<String>,
+    /// API key, redacted when printed
+    /// Optional, read from log_api_key_file if not set
+    pub log_api_key: Option<Secret>,
+    /// File the API key is read from, e.g. a mounted Docker or Kubernetes secret
+    /// Optional, defaults to the log_api_key systemd credential if $CREDENTIALS_DIRECTORY has
+    /// one. Read again on reload, so a rotated key is used without restarting.
+    pub log_api_key_file: Option<String>,
     /// Scanner location
-    /// Optional in .env file, defaults to 'dev-location'
+    /// Optional, defaults to 'dev-location'
     pub location: String,
+    /// Kinds of ARP packets that count as a device being present
+    /// Optional, defaults to 'reply,gratuitous', or every kind in passive mode
+    pub arp_presence_policy: PresencePolicy,
+    /// DHCP and mDNS chatter that also counts as a device being present
+    /// Optional as a comma separated list of dhcp and mdns, defaults to none
+    pub passive_sources: PassiveSources,
+    /// mDNS and SSDP announcements used to attach names, service types and models to devices
+    /// Optional as a comma separated list of mdns and ssdp, defaults to none
+    pub announcement_sources: AnnouncementSources,
+    /// Devices that are never counted, based on what they announced, their vendor or class
+    /// Optional as lists in devices.exclude_service_types, exclude_models, exclude_vendors and
+    /// exclude_classes, nothing is excluded by default
+    pub exclusions: ExclusionRules,
+    /// Seconds within which a randomized mac showing up with the ip, host name or DHCP
+    /// fingerprint of a device that left is counted as the same device
+    /// Optional, randomized macs are not de-duplicated if not set
+    pub randomized_dedup_window: Option<u64>,
+    /// Whether devices are cached and logged under pseudonyms instead of their mac addresses
+    /// Optional, defaults to false. Capture is not allowed in privacy mode.
+    pub privacy: bool,
+    /// Key of the pseudonyms, so they are stable across restarts within a day, redacted when
+    /// printed
+    /// Optional, a random key is generated on every start if not set
+    pub privacy_key: Option<Secret>,
+    /// IEEE oui.csv or oui.txt file used to resolve vendors
+    /// Optional, a small embedded database of common vendors is used if not set
+    pub oui_db_path: Option<String>,
+    /// Rules classifying devices, tried before the default ones
+    /// Optional as a comma separated list of field:pattern=class
+    pub class_rules: Vec<ClassRule>,
+    /// Whether IPv6 neighbors are discovered and counted through NDP
+    /// Optional, defaults to false
+    pub ndp: bool,
+    /// Records ARP traffic to a pcapng file
+    /// Optional, enabled by setting sinks.capture_path
+    pub capture: Option<CaptureOptions>,
+    /// Interfaces to scan, each with its own device count
+    /// Optional as a list of selector[:location] or of tables with a selector and location,
+    /// the default interface is scanned if empty
+    pub interfaces: Vec<InterfaceOptions>,
+    /// IPv4 addresses that are scanned and counted
+    /// Optional as lists of cidrs, start-end ranges and hosts in targets.include and
+    /// targets.exclude, the whole interface subnet is scanned if no target is set
+    pub targets: TargetSet,
 }
 
-fn load_env_var<T>(key: &str) -> T
-where
-    T: FromStr,
-    T::Err: Debug,
-{
-    dotenvy::var(key)
-        .unwrap_or_else(|_| panic!("unable to load {key}"))
-        .parse()
-        .unwrap_or_else(|_| panic!("unable to parse {key}"))
+/// How the scanner finds devices
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanMode {
+    /// Sends ARP requests (and NDP solicitations) and counts the answers
+    Active,
+    /// Sends nothing, counts devices from traffic they generate themselves
+    Passive,
+}
+
+impl FromStr for ScanMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "active" => Ok(ScanMode::Active),
+            "passive" => Ok(ScanMode::Passive),
+            _ => Err(format!("unknown scan mode: {s}")),
+        }
+    }
+}
+
+impl Display for ScanMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanMode::Active => write!(f, "active"),
+            ScanMode::Passive => write!(f, "passive"),
+        }
+    }
+}
+
+/// Interface scanned by the scanner
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceOptions {
+    /// Name (e.g. eth0.10), mac address or ip/cidr of the interface
+    pub selector: InterfaceSelector,
+    /// Location its device count is logged as, defaults to the scanner location
+    pub location: Option<String>,
+}
+
+impl FromStr for InterfaceOptions {
+    type Err = String;
+
+    /// Parses selector[:location]
+    /// Mac addresses and ipv6 addresses contain colons themselves, so the whole entry is tried
+    /// as a mac or ip before splitting off the location at the last colon.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let (selector, location) = match s.parse::<InterfaceSelector>()? {
+            InterfaceSelector::Name(_) => match s.rsplit_once(':') {
+                Some((selector, location)) => (selector.trim().parse()?, Some(location.trim())),
+                None => (InterfaceSelector::Name(String::from(s)), None),
+            },
+            selector => (selector, None),
+        };
+
+        Ok(Self {
+            selector,
+            location: location.filter(|l| !l.is_empty()).map(String::from),
+        })
+    }
+}
+
+/// Where and how ARP traffic is recorded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureOptions {
+    /// pcapng file that is written to, rotated files get a numbered suffix
+    pub path: String,
+    /// Size after which the file is rotated, in bytes
+    /// Optional, defaults to 64MiB
+    pub rotate_bytes: u64,
+    /// Number of files kept, including the current one
+    /// Optional, defaults to 5
+    pub max_files: usize,
+}
+
+/// Config file keys, with the environment variables that override them
+///
+/// Keys in a section are written as section.key, e.g. timing.mac_addr_timeout_secs is
+/// mac_addr_timeout_secs in the [timing] table of the config file.
+pub const CONFIG_KEYS: [(&str, &str); 38] = [
+    ("mode", "SCAN_MODE"),
+    ("location", "SCANNER_LOCATION"),
+    ("trace", "TRACE"),
+    ("reconnect_cmd", "RECONNECT_CMD"),
+    ("interfaces", "INTERFACES"),
+    ("timing.mac_addr_timeout_secs", "MAC_ADDR_TIMEOUT_SECS"),
+    ("timing.arp_scan_period_secs", "ARP_SCAN_PERIOD_SECS"),
+    (
+        "timing.mac_cache_log_period_secs",
+        "MAC_CACHE_LOG_PERIOD_SECS",
+    ),
+    ("timing.max_pps", "ARP_MAX_PPS"),
+    ("timing.burst_size", "ARP_BURST_SIZE"),
+    ("timing.randomize_targets", "ARP_RANDOMIZE_TARGETS"),
+    (
+        "timing.unknown_sweep_period_secs",
+        "ARP_UNKNOWN_SWEEP_PERIOD_SECS",
+    ),
+    ("timing.reprobe_retries", "ARP_REPROBE_RETRIES"),
+    ("timing.reprobe_interval_secs", "ARP_REPROBE_INTERVAL_SECS"),
+    ("targets.include", "ARP_TARGETS"),
+    ("targets.exclude", "ARP_EXCLUDE"),
+    ("sinks.api_url", "LOG_API_URL"),
+    ("sinks.api_key", "LOG_API_KEY"),
+    ("sinks.api_key_file", "LOG_API_KEY_FILE"),
+    ("sinks.api_retry_limit", "API_RETRY_LIMIT"),
+    ("sinks.capture_path", "CAPTURE_PATH"),
+    ("sinks.capture_rotate_bytes", "CAPTURE_ROTATE_BYTES"),
+    ("sinks.capture_max_files", "CAPTURE_MAX_FILES"),
+    ("detection.presence_policy", "ARP_PRESENCE_POLICY"),
+    ("detection.passive_sources", "PASSIVE_SOURCES"),
+    ("detection.announcement_sources", "ANNOUNCEMENT_SOURCES"),
+    ("detection.ndp", "NDP_ENABLED"),
+    ("devices.oui_db_path", "OUI_DB_PATH"),
+    ("devices.class_rules", "DEVICE_CLASS_RULES"),
+    (
+        "devices.randomized_dedup_window_secs",
+        "RANDOMIZED_DEDUP_WINDOW_SECS",
+    ),
+    ("devices.exclude_service_types", "EXCLUDE_SERVICE_TYPES"),
+    ("devices.exclude_models", "EXCLUDE_MODELS"),
+    ("devices.exclude_vendors", "EXCLUDE_VENDORS"),
+    ("devices.exclude_classes", "EXCLUDE_CLASSES"),
+    ("privacy.enabled", "PRIVACY_MODE"),
+    ("privacy.key", "PRIVACY_KEY"),
+    // tables of [[interfaces]]
+    ("interfaces.selector", ""),
+    ("interfaces.location", ""),
+];
+
+const SECONDS: &str = "a whole number of seconds";
+const COUNT: &str = "a whole number";
+const BOOL: &str = "true or false";
+const TEXT: &str = "a string";
+
+/// Where a configuration value was taken from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// TOML config file at the path
+    File(PathBuf),
+    /// Environment variable of the process
+    Env(&'static str),
+    /// Variable of the .env file at the path
+    EnvFile(PathBuf, &'static str),
+    /// Command line flag
+    Cli(String),
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::File(path) => write!(f, "config file {}", path.display()),
+            ConfigSource::Env(var) => write!(f, "environment variable {var}"),
+            ConfigSource::EnvFile(path, var) => write!(f, "{} in {}", var, path.display()),
+            ConfigSource::Cli(flag) => write!(f, "command line flag {flag}"),
+        }
+    }
+}
+
+struct Override {
+    key: &'static str,
+    value: String,
+    flag: String,
+}
+
+/// Layers scanner options are loaded from
+///
+/// Each option is taken from the first layer that sets it, in order: command line overrides,
+/// environment variables, the .env file, the TOML config file and finally the defaults. An
+/// empty value unsets the option in lower layers. Lists can be given as TOML arrays or as comma
+/// separated strings.
+pub struct ConfigLayers {
+    file: Option<(PathBuf, Table)>,
+    /// Environment of the process when the layers were created
+    env: HashMap<String, String>,
+    env_file: Option<(PathBuf, HashMap<String, String>)>,
+    overrides: Vec<Override>,
+}
+
+impl Default for ConfigLayers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigLayers {
+    /// Layers with only the environment and defaults
+    ///
+    /// The environment is read once, here, and is not affected by later changes to it.
+    pub fn new() -> Self {
+        Self::with_env(
+            env::vars_os()
+                .filter_map(|(var, value)| {
+                    Some((var.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+        )
+    }
+
+    /// Layers with vars as environment instead of the environment of the process, and defaults
+    pub fn with_env(vars: HashMap<String, String>) -> Self {
+        Self {
+            file: None,
+            env: vars,
+            env_file: None,
+            overrides: vec![],
+        }
+    }
+
+    /// Adds the TOML config file at path, failing on unknown keys
+    pub fn with_file(mut self, path: &Path) -> Result<Self, ConfigErr> {
+        let contents = fs::read_to_string(path).map_err(|error| ConfigErr::ReadFile {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        let table: Table = toml::from_str(&contents).map_err(|error| ConfigErr::ParseFile {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        check_keys(&table, "", path)?;
+        self.file = Some((path.to_path_buf(), table));
+        Ok(self)
+    }
+
+    /// Adds the variables of the .env file at path, below the environment of the process
+    ///
+    /// The process environment is left as it is, so the file can be reloaded while threads
+    /// that read the environment are running. A variable set twice in the file takes its last
+    /// value.
+    pub fn with_env_file(mut self, path: &Path) -> Result<Self, ConfigErr> {
+        let error = |error| ConfigErr::EnvFile {
+            path: path.to_path_buf(),
+            error,
+        };
+
+        let mut vars = HashMap::new();
+        for var in dotenvy::from_path_iter(path).map_err(error)? {
+            let (key, value) = var.map_err(error)?;
+            vars.insert(key, value);
+        }
+
+        self.env_file = Some((path.to_path_buf(), vars));
+        Ok(self)
+    }
+
+    /// Overrides key with value, given on the command line through flag
+    pub fn with_override(mut self, key: &str, value: &str, flag: &str) -> Result<Self, ConfigErr> {
+        let key = match env_var(key) {
+            Some((key, env)) if !env.is_empty() => key,
+            _ => {
+                return Err(ConfigErr::UnknownKey {
+                    key: String::from(key),
+                    source: ConfigSource::Cli(String::from(flag)),
+                })
+            }
+        };
+
+        self.overrides.push(Override {
+            key,
+            value: String::from(value),
+            flag: String::from(flag),
+        });
+        Ok(self)
+    }
+
+    /// Value of key in the first layer setting it, and that layer
+    fn lookup(&self, key: &'static str) -> Option<(Value, ConfigSource)> {
+        if let Some(o) = self.overrides.iter().rev().find(|o| o.key == key) {
+            return Some((
+                Value::String(o.value.clone()),
+                ConfigSource::Cli(o.flag.clone()),
+            ));
+        }
+
+        if let Some((_, env)) = env_var(key) {
+            if let Some(value) = self.env.get(env) {
+                return Some((Value::String(value.clone()), ConfigSource::Env(env)));
+            }
+
+            if let Some((path, vars)) = &self.env_file {
+                if let Some(value) = vars.get(env) {
+                    return Some((
+                        Value::String(value.clone()),
+                        ConfigSource::EnvFile(path.clone(), env),
+                    ));
+                }
+            }
+        }
+
+        let (path, table) = self.file.as_ref()?;
+        let mut parts = key.split('.');
+        let mut value = table.get(parts.next()?)?;
+        for part in parts {
+            value = value.as_table()?.get(part)?;
+        }
+
+        Some((value.clone(), ConfigSource::File(path.clone())))
+    }
+
+    fn value<T>(&self, key: &'static str, expected: &'static str) -> Result<Option<T>, ConfigErr>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.lookup(key) {
+            Some((value, source)) => parse_scalar(key, &value, &source, expected),
+            None => Ok(None),
+        }
+    }
+
+    fn required<T>(&self, key: &'static str, expected: &'static str) -> Result<T, ConfigErr>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.value(key, expected)?.ok_or(ConfigErr::Missing {
+            key,
+            env: env_var(key).map_or("", |(_, env)| env),
+        })
+    }
+
+    fn list<T>(&self, key: &'static str, expected: &'static str) -> Result<Vec<T>, ConfigErr>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let (value, source) = match self.lookup(key) {
+            Some(found) => found,
+            None => return Ok(vec![]),
+        };
+
+        let items = match value {
+            Value::Array(items) => items,
+            Value::String(s) => s.split(',').map(Value::from).collect(),
+            value => vec![value],
+        };
+
+        let mut parsed = vec![];
+        for item in &items {
+            if let Some(item) = parse_scalar(key, item, &source, expected)? {
+                parsed.push(item);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Value of a key holding a comma separated set, which can also be given as an array
+    fn set<T>(&self, key: &'static str, expected: &'static str) -> Result<Option<T>, ConfigErr>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.lookup(key) {
+            Some((Value::Array(items), source)) => {
+                let items: Vec<String> = items.iter().map(scalar_text).collect();
+                parse_scalar(key, &Value::from(items.join(",")), &source, expected)
+            }
+            Some((value, source)) => parse_scalar(key, &value, &source, expected),
+            None => Ok(None),
+        }
+    }
+
+    fn interfaces(&self) -> Result<Vec<InterfaceOptions>, ConfigErr> {
+        let expected = "a list of selector[:location]";
+
+        let (tables, source) = match self.lookup("interfaces") {
+            Some((Value::Array(items), source)) if items.iter().any(Value::is_table) => {
+                (items, source)
+            }
+            _ => return self.list("interfaces", expected),
+        };
+
+        let mut interfaces = vec![];
+        for table in &tables {
+            let selector = match table.get("selector") {
+                Some(selector) => selector,
+                None => {
+                    return Err(ConfigErr::Invalid {
+                        key: "interfaces",
+                        source,
+                        expected: "a table with a selector",
+                        value: table.to_string(),
+                        reason: None,
+                    })
+                }
+            };
+
+            interfaces.push(InterfaceOptions {
+                selector: parse_scalar("interfaces.selector", selector, &source, expected)?
+                    .ok_or_else(|| invalid("interfaces.selector", &source, expected, selector))?,
+                location: match table.get("location") {
+                    Some(location) => parse_scalar("interfaces.location", location, &source, TEXT)?,
+                    None => None,
+                },
+            });
+        }
+
+        Ok(interfaces)
+    }
+}
+
+/// Loads scanner options from layers
+///
+/// Fails naming the key and the layer it came from if a required option is missing or a value
+/// cannot be parsed.
+pub fn load_scanner_opts(layers: &ConfigLayers) -> Result<ScannerOptions, ConfigErr> {
+    let mode = layers
+        .value("mode", "active or passive")?
+        .unwrap_or(ScanMode::Active);
+
+    let capture = match layers.value::<String>("sinks.capture_path", TEXT)? {
+        Some(path) => Some(CaptureOptions {
+            path,
+            rotate_bytes: layers
+                .value("sinks.capture_rotate_bytes", "a whole number of bytes")?
+                .unwrap_or(DEFAULT_CAPTURE_ROTATE_BYTES),
+            max_files: layers
+                .value("sinks.capture_max_files", COUNT)?
+                .unwrap_or(DEFAULT_CAPTURE_MAX_FILES),
+        }),
+        None => None,
+    };
+
+    let (log_api_key, log_api_key_file) = api_key(layers)?;
+
+    Ok(ScannerOptions {
+        mode,
+        mac_addr_timeout: layers.required("timing.mac_addr_timeout_secs", SECONDS)?,
+        arp_scan_period: layers.required("timing.arp_scan_period_secs", SECONDS)?,
+        max_pps: layers.value("timing.max_pps", "a whole number of packets per second")?,
+        burst_size: layers
+            .value("timing.burst_size", COUNT)?
+            .unwrap_or(DEFAULT_BURST_SIZE),
+        randomize_targets: layers
+            .value("timing.randomize_targets", BOOL)?
+            .unwrap_or(true),
+        unknown_sweep_period: layers.value("timing.unknown_sweep_period_secs", SECONDS)?,
+        reprobe_retries: layers
+            .value("timing.reprobe_retries", COUNT)?
+            .unwrap_or(DEFAULT_REPROBE_RETRIES),
+        reprobe_interval: layers
+            .value("timing.reprobe_interval_secs", SECONDS)?
+            .unwrap_or(DEFAULT_REPROBE_INTERVAL_SECS),
+        mac_cache_log_period: layers.required("timing.mac_cache_log_period_secs", SECONDS)?,
+        trace: layers.required("trace", BOOL)?,
+        reconnect_cmd: layers.required("reconnect_cmd", TEXT)?,
+        log_api_url: layers.value("sinks.api_url", TEXT)?,
+        api_retry_limit: layers.value("sinks.api_retry_limit", COUNT)?,
+        log_api_key,
+        log_api_key_file,
+        location: layers
+            .value("location", TEXT)?
+            .unwrap_or_else(|| String::from("dev-location")),
+        arp_presence_policy: layers
+            .set(
+                "detection.presence_policy",
+                "a list of reply, gratuitous, request and probe",
+            )?
+            .unwrap_or(match mode {
+                ScanMode::Active => PresencePolicy::default(),
+                // nothing is answering requests, every packet a device sends itself counts
+                ScanMode::Passive => PresencePolicy::all(),
+            }),
+        passive_sources: layers
+            .set("detection.passive_sources", "a list of dhcp and mdns")?
+            .unwrap_or_default(),
+        announcement_sources: layers
+            .set("detection.announcement_sources", "a list of mdns and ssdp")?
+            .unwrap_or_default(),
+        exclusions: ExclusionRules::new(
+            layers.list("devices.exclude_service_types", "a list of service types")?,
+            layers.list("devices.exclude_models", "a list of model patterns")?,
+            layers.list("devices.exclude_vendors", "a list of vendor patterns")?,
+            layers.list(
+                "devices.exclude_classes",
+                "a list of phone, laptop, network, iot and unknown",
+            )?,
+        ),
+        randomized_dedup_window: layers.value("devices.randomized_dedup_window_secs", SECONDS)?,
+        privacy: layers.value("privacy.enabled", BOOL)?.unwrap_or(false),
+        privacy_key: layers.value("privacy.key", TEXT)?,
+        oui_db_path: layers.value("devices.oui_db_path", TEXT)?,
+        class_rules: layers.list("devices.class_rules", "a list of field:pattern=class")?,
+        ndp: layers.value("detection.ndp", BOOL)?.unwrap_or(false),
+        capture,
+        interfaces: layers.interfaces()?,
+        targets: TargetSet::new(
+            layers.list(
+                "targets.include",
+                "a list of cidrs, start-end ranges and hosts",
+            )?,
+            layers.list(
+                "targets.exclude",
+                "a list of cidrs, start-end ranges and hosts",
+            )?,
+        ),
+    })
+}
+
+/// API key and the file it was read from
+///
+/// The key is taken from sinks.api_key, read from sinks.api_key_file, or read from the
+/// log_api_key systemd credential, in that order.
+fn api_key(layers: &ConfigLayers) -> Result<(Option<Secret>, Option<String>), ConfigErr> {
+    let key = layers.value::<Secret>("sinks.api_key", TEXT)?;
+    let file = layers.value::<String>("sinks.api_key_file", TEXT)?;
+
+    let path = match (key, file) {
+        (Some(_), Some(_)) => {
+            return Err(ConfigErr::Conflict {
+                key: "sinks.api_key",
+                other: "sinks.api_key_file",
+            })
+        }
+        (Some(key), None) => return Ok((Some(key), None)),
+        (None, Some(path)) => path,
+        (None, None) => match layers.env.get("CREDENTIALS_DIRECTORY") {
+            Some(dir) if Path::new(&dir).join(API_KEY_CREDENTIAL).is_file() => Path::new(&dir)
+                .join(API_KEY_CREDENTIAL)
+                .to_string_lossy()
+                .into_owned(),
+            _ => return Ok((None, None)),
+        },
+    };
+
+    let key = Secret::read(Path::new(&path)).map_err(|error| ConfigErr::SecretFile {
+        path: PathBuf::from(&path),
+        error,
+    })?;
+
+    Ok((Some(key), Some(path)))
+}
+
+/// Known key and its environment variable
+fn env_var(key: &str) -> Option<(&'static str, &'static str)> {
+    CONFIG_KEYS.iter().find(|(known, _)| *known == key).copied()
 }
 
-fn load_env_var_optional<T>(key: &str) -> Option<T>
+/// Fails on keys of table that are not known, so typos are not silently ignored
+fn check_keys(table: &Table, prefix: &str, path: &Path) -> Result<(), ConfigErr> {
+    for (name, value) in table {
+        let key = format!("{prefix}{name}");
+        let is_section = CONFIG_KEYS
+            .iter()
+            .any(|(known, env)| !env.is_empty() && known.starts_with(&format!("{key}.")));
+
+        match value {
+            Value::Table(section) if is_section => check_keys(section, &format!("{key}."), path)?,
+            Value::Array(items) if key == "interfaces" => {
+                for item in items.iter().filter_map(Value::as_table) {
+                    check_keys(item, "interfaces.", path)?;
+                }
+            }
+            _ if env_var(&key).is_some() => {}
+            _ => {
+                return Err(ConfigErr::UnknownKey {
+                    key,
+                    source: ConfigSource::File(path.to_path_buf()),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Text of a TOML string, number or bool
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.trim().to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Parses a TOML string, number or bool, empty strings are unset
+fn parse_scalar<T>(
+    key: &'static str,
+    value: &Value,
+    source: &ConfigSource,
+    expected: &'static str,
+) -> Result<Option<T>, ConfigErr>
 where
     T: FromStr,
+    T::Err: Display,
 {
-    match dotenvy::var(key) {
-        Ok(v) => v.parse().ok(),
-        Err(_) => None,
-    }
-}
-
-// Reuseable wrapper around Command
-pub fn load_scanner_opts() -> ScannerOptions {
-    ScannerOptions {
-        mac_addr_timeout: load_env_var("MAC_ADDR_TIMEOUT_SECS"),
-        arp_scan_period: load_env_var("ARP_SCAN_PERIOD_SECS"),
-        mac_cache_log_period: load_env_var("MAC_CACHE_LOG_PERIOD_SECS"),
-        trace: load_env_var("TRACE"),
-        reconnect_cmd: load_env_var("RECONNECT_CMD"),
-        log_api_url: load_env_var_optional("LOG_API_URL"),
-        api_retry_limit: load_env_var_optional("API_RETRY_LIMIT"),
-        log_api_key: load_env_var_optional("LOG_API_KEY"),
-        location: load_env_var_optional("SCANNER_LOCATION")
-            .unwrap_or_else(|| String::from("dev-location")),
+    if value.is_array() || value.is_table() || value.is_datetime() {
+        return Err(invalid(key, source, expected, value));
+    }
+
+    let text = scalar_text(value);
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    text.parse()
+        .map(Some)
+        .map_err(|e: T::Err| ConfigErr::Invalid {
+            key,
+            source: source.clone(),
+            expected,
+            value: text,
+            reason: Some(e.to_string()),
+        })
+}
+
+fn invalid(
+    key: &'static str,
+    source: &ConfigSource,
+    expected: &'static str,
+    value: &Value,
+) -> ConfigErr {
+    ConfigErr::Invalid {
+        key,
+        source: source.clone(),
+        expected,
+        value: value.to_string(),
+        reason: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: &str = "
+trace = false
+reconnect_cmd = \"true\"
+
+[timing]
+mac_addr_timeout_secs = 300
+arp_scan_period_secs = 1
+mac_cache_log_period_secs = 5
+";
+
+    /// Writes contents to a config file only used by test
+    fn config_file(test: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ark-scanner-{}-{test}.toml", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Layers of the file with vars as environment, instead of the environment of the process
+    fn layers(
+        test: &str,
+        contents: &str,
+        vars: &[(&str, &str)],
+    ) -> Result<ConfigLayers, ConfigErr> {
+        let path = config_file(test, contents);
+        let layers = ConfigLayers::with_env(
+            vars.iter()
+                .map(|(var, value)| (String::from(*var), String::from(*value)))
+                .collect(),
+        )
+        .with_file(&path);
+        fs::remove_file(path).unwrap();
+        layers
+    }
+
+    #[test]
+    fn env_overrides_file_and_set_overrides_env() {
+        let layers = layers(
+            "precedence",
+            REQUIRED,
+            &[
+                ("MAC_ADDR_TIMEOUT_SECS", "60"),
+                ("ARP_SCAN_PERIOD_SECS", "2"),
+            ],
+        )
+        .unwrap()
+        .with_override("timing.arp_scan_period_secs", "3", "--set")
+        .unwrap();
+
+        let (_, source) = layers.lookup("timing.mac_cache_log_period_secs").unwrap();
+        assert!(matches!(source, ConfigSource::File(_)));
+        assert_eq!(
+            layers.lookup("timing.mac_addr_timeout_secs").unwrap().1,
+            ConfigSource::Env("MAC_ADDR_TIMEOUT_SECS")
+        );
+
+        let options = load_scanner_opts(&layers).unwrap();
+        assert_eq!(options.mac_cache_log_period, 5);
+        assert_eq!(options.mac_addr_timeout, 60);
+        assert_eq!(options.arp_scan_period, 3);
+    }
+
+    #[test]
+    fn env_overrides_env_file() {
+        let env_file = env::temp_dir().join(format!("ark-scanner-{}.env", std::process::id()));
+        fs::write(
+            &env_file,
+            "MAC_ADDR_TIMEOUT_SECS=60\nARP_SCAN_PERIOD_SECS=2\nARP_SCAN_PERIOD_SECS=4\n",
+        )
+        .unwrap();
+
+        let layers = layers("env-file", REQUIRED, &[("MAC_ADDR_TIMEOUT_SECS", "30")])
+            .unwrap()
+            .with_env_file(&env_file);
+        fs::remove_file(env_file).unwrap();
+
+        let options = load_scanner_opts(&layers.unwrap()).unwrap();
+        assert_eq!(options.mac_addr_timeout, 30);
+        // last of the duplicated variables
+        assert_eq!(options.arp_scan_period, 4);
+    }
+
+    #[test]
+    fn later_set_overrides_earlier_one() {
+        let layers = layers("repeated-set", REQUIRED, &[])
+            .unwrap()
+            .with_override("location", "a", "--set")
+            .unwrap()
+            .with_override("location", "b", "--set")
+            .unwrap();
+
+        assert_eq!(load_scanner_opts(&layers).unwrap().location, "b");
+    }
+
+    #[test]
+    fn empty_value_unsets_lower_layers() {
+        let layers = layers(
+            "empty",
+            &format!("{REQUIRED}\n[sinks]\napi_url = \"http://x\"\n"),
+            &[("LOG_API_URL", "")],
+        )
+        .unwrap();
+
+        assert_eq!(load_scanner_opts(&layers).unwrap().log_api_url, None);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for (test, contents, unknown) in [
+            ("unknown-top", "scan_mode = \"active\"", "scan_mode"),
+            (
+                "unknown-section",
+                "[timing]\nscan_period = 1",
+                "timing.scan_period",
+            ),
+            ("unknown-table", "[timings]\nmax_pps = 1", "timings"),
+            (
+                "unknown-interface",
+                "[[interfaces]]\nselector = \"eth0\"\nname = \"a\"",
+                "interfaces.name",
+            ),
+        ] {
+            match layers(test, contents, &[]) {
+                Err(ConfigErr::UnknownKey { key, .. }) => assert_eq!(key, unknown),
+                _ => panic!("{unknown} was not rejected"),
+            }
+        }
+
+        match ConfigLayers::new().with_override("timing.scan_period", "1", "--set") {
+            Err(ConfigErr::UnknownKey { key, source }) => {
+                assert_eq!(key, "timing.scan_period");
+                assert_eq!(source, ConfigSource::Cli(String::from("--set")));
+            }
+            _ => panic!("unknown --set key was not rejected"),
+        }
+
+        // keys of [[interfaces]] tables only exist inside the tables
+        assert!(ConfigLayers::new()
+            .with_override("interfaces.selector", "eth0", "--set")
+            .is_err());
+    }
+
+    #[test]
+    fn interface_is_parsed_as_selector_and_location() {
+        let parse = |s: &str| s.parse::<InterfaceOptions>().unwrap();
+        let interface = |selector: &str, location: Option<&str>| InterfaceOptions {
+            selector: selector.parse().unwrap(),
+            location: location.map(String::from),
+        };
+
+        assert_eq!(parse("eth0.10"), interface("eth0.10", None));
+        assert_eq!(
+            parse("eth0.10:room-a"),
+            interface("eth0.10", Some("room-a"))
+        );
+        assert_eq!(parse(" eth0 : lobby "), interface("eth0", Some("lobby")));
+        assert_eq!(parse("eth0:"), interface("eth0", None));
+        assert_eq!(
+            parse("aa:bb:cc:dd:ee:ff"),
+            interface("aa:bb:cc:dd:ee:ff", None)
+        );
+        assert_eq!(
+            parse("aa:bb:cc:dd:ee:ff:room-a"),
+            interface("aa:bb:cc:dd:ee:ff", Some("room-a"))
+        );
+        assert_eq!(
+            parse("192.168.1.0/24:lobby"),
+            interface("192.168.1.0/24", Some("lobby"))
+        );
+        assert_eq!(parse("fe80::1"), interface("fe80::1", None));
+        assert!(":lobby".parse::<InterfaceOptions>().is_err());
+    }
+
+    #[test]
+    fn interfaces_are_read_from_lists_and_tables() {
+        let list = layers(
+            "interface-list",
+            REQUIRED,
+            &[("INTERFACES", "eth0:a, eth1")],
+        )
+        .unwrap();
+        assert_eq!(
+            load_scanner_opts(&list).unwrap().interfaces,
+            vec![
+                "eth0:a".parse::<InterfaceOptions>().unwrap(),
+                "eth1".parse().unwrap()
+            ]
+        );
+
+        let tables = layers(
+            "interface-tables",
+            &format!(
+                "interfaces = [{{ selector = \"10.0.0.0/24\", location = \"lobby\" }}, \
+                 {{ selector = \"eth1\" }}]\n{REQUIRED}"
+            ),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            load_scanner_opts(&tables).unwrap().interfaces,
+            vec![
+                "10.0.0.0/24:lobby".parse::<InterfaceOptions>().unwrap(),
+                "eth1".parse().unwrap()
+            ]
+        );
+
+        let missing = layers(
+            "interface-no-selector",
+            &format!("interfaces = [{{ location = \"lobby\" }}]\n{REQUIRED}"),
+            &[],
+        )
+        .unwrap();
+        assert!(matches!(
+            load_scanner_opts(&missing),
+            Err(ConfigErr::Invalid {
+                key: "interfaces",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn api_key_and_key_file_conflict() {
+        let layers = layers(
+            "conflict",
+            &format!("{REQUIRED}\n[sinks]\napi_key_file = \"/run/secrets/key\"\n"),
+            &[("LOG_API_KEY", "key")],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            load_scanner_opts(&layers),
+            Err(ConfigErr::Conflict {
+                key: "sinks.api_key",
+                other: "sinks.api_key_file"
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_and_invalid_values_name_their_key() {
+        let missing = layers("missing", "trace = false", &[]).unwrap();
+        assert!(matches!(
+            load_scanner_opts(&missing),
+            Err(ConfigErr::Missing {
+                key: "timing.mac_addr_timeout_secs",
+                env: "MAC_ADDR_TIMEOUT_SECS"
+            })
+        ));
+
+        let invalid = layers("invalid", REQUIRED, &[("ARP_SCAN_PERIOD_SECS", "1s")]).unwrap();
+        match load_scanner_opts(&invalid) {
+            Err(ConfigErr::Invalid {
+                key, source, value, ..
+            }) => {
+                assert_eq!(key, "timing.arp_scan_period_secs");
+                assert_eq!(source, ConfigSource::Env("ARP_SCAN_PERIOD_SECS"));
+                assert_eq!(value, "1s");
+            }
+            _ => panic!("invalid value was accepted"),
+        }
     }
 }
@@ -0,0 +1,269 @@
+//   DHCP message format:
+// - https://www.rfc-editor.org/rfc/rfc2131#section-2
+// - https://www.rfc-editor.org/rfc/rfc2132 (options)
+
+use std::net::Ipv4Addr;
+
+use pnet_datalink::MacAddr;
+
+const BOOTREQUEST: u8 = 1;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+// fixed part of the message, up to and including the magic cookie
+const OPTIONS_OFFSET: usize = 240;
+
+const OPT_PAD: u8 = 0;
+const OPT_HOSTNAME: u8 = 12;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPT_VENDOR_CLASS: u8 = 60;
+const OPT_END: u8 = 255;
+
+/// Type of a DHCP message, from option 53
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl DhcpMessageType {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(DhcpMessageType::Discover),
+            2 => Some(DhcpMessageType::Offer),
+            3 => Some(DhcpMessageType::Request),
+            4 => Some(DhcpMessageType::Decline),
+            5 => Some(DhcpMessageType::Ack),
+            6 => Some(DhcpMessageType::Nak),
+            7 => Some(DhcpMessageType::Release),
+            8 => Some(DhcpMessageType::Inform),
+            _ => None,
+        }
+    }
+}
+
+/// DHCP message sent by a client, parsed from a UDP payload
+pub struct DhcpObservation {
+    pub message_type: DhcpMessageType,
+    /// Client hardware address (chaddr)
+    pub client_mac: MacAddr,
+    /// Address the client already uses (ciaddr), if any
+    pub client_ip: Option<Ipv4Addr>,
+    /// Address the client asks for (option 50)
+    pub requested_ip: Option<Ipv4Addr>,
+    /// Host name of the client (option 12)
+    pub hostname: Option<String>,
+    /// Vendor class identifier of the client, e.g. 'MSFT 5.0' or 'android-dhcp-13' (option 60)
+    pub vendor_class: Option<String>,
+    /// Options the client asks for, in its order, e.g. '1,121,3,6,15' (option 55)
+    /// The list is specific to the client's operating system and DHCP stack.
+    pub fingerprint: Option<String>,
+}
+
+impl DhcpObservation {
+    /// Parses a DHCP client message, returns None for server messages or malformed payloads
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < OPTIONS_OFFSET
+            || payload[0] != BOOTREQUEST
+            || payload[1] != HTYPE_ETHERNET
+            || payload[2] != 6
+            || payload[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let ciaddr = Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]);
+        let chaddr = &payload[28..34];
+
+        let mut message_type = None;
+        let mut requested_ip = None;
+        let mut hostname = None;
+        let mut vendor_class = None;
+        let mut fingerprint = None;
+
+        let mut options = &payload[OPTIONS_OFFSET..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPT_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+
+            let (&len, rest) = rest.split_first()?;
+            let value = rest.get(..len as usize)?;
+            options = &rest[len as usize..];
+
+            match code {
+                OPT_MESSAGE_TYPE => {
+                    message_type = value.first().copied().and_then(DhcpMessageType::from_code)
+                }
+                OPT_REQUESTED_IP if value.len() == 4 => {
+                    requested_ip = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                }
+                OPT_HOSTNAME => hostname = text_option(value),
+                OPT_VENDOR_CLASS => vendor_class = text_option(value),
+                OPT_PARAMETER_REQUEST_LIST if !value.is_empty() => {
+                    let codes: Vec<String> = value.iter().map(u8::to_string).collect();
+                    fingerprint = Some(codes.join(","));
+                }
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            message_type: message_type?,
+            client_mac: MacAddr::new(
+                chaddr[0], chaddr[1], chaddr[2], chaddr[3], chaddr[4], chaddr[5],
+            ),
+            client_ip: Some(ciaddr).filter(|ip| !ip.is_unspecified()),
+            requested_ip,
+            hostname,
+            vendor_class,
+            fingerprint,
+        })
+    }
+
+    /// Address the client uses or is about to use
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        self.client_ip.or(self.requested_ip)
+    }
+}
+
+// Options are not null terminated, but some clients add one anyway
+// Control characters are dropped, the text ends up in logs.
+fn text_option(value: &[u8]) -> Option<String> {
+    let text: String = String::from_utf8_lossy(value)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    let text = text.trim();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(String::from(text))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const CLIENT: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+
+    /// Client message from CLIENT at ciaddr, followed by options and an end option
+    pub(crate) fn client_message(ciaddr: Ipv4Addr, options: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0; OPTIONS_OFFSET];
+        payload[0] = BOOTREQUEST;
+        payload[1] = HTYPE_ETHERNET;
+        payload[2] = 6;
+        payload[12..16].copy_from_slice(&ciaddr.octets());
+        payload[28..34]
+            .copy_from_slice(&[CLIENT.0, CLIENT.1, CLIENT.2, CLIENT.3, CLIENT.4, CLIENT.5]);
+        payload[236..240].copy_from_slice(&MAGIC_COOKIE);
+        payload.extend_from_slice(options);
+        payload.push(OPT_END);
+        payload
+    }
+
+    /// Option with its length
+    fn option(code: u8, value: &[u8]) -> Vec<u8> {
+        [&[code, value.len() as u8], value].concat()
+    }
+
+    /// Release of the lease of ciaddr by CLIENT
+    pub(crate) fn release(ciaddr: Ipv4Addr) -> Vec<u8> {
+        client_message(ciaddr, &option(OPT_MESSAGE_TYPE, &[7]))
+    }
+
+    #[test]
+    fn request_is_parsed_with_its_options() {
+        let options = [
+            option(OPT_MESSAGE_TYPE, &[3]),
+            vec![OPT_PAD],
+            option(OPT_REQUESTED_IP, &[10, 0, 0, 7]),
+            // null terminated, with a control character
+            option(OPT_HOSTNAME, b"ph\x07one\0"),
+            option(OPT_VENDOR_CLASS, b"MSFT 5.0"),
+            option(OPT_PARAMETER_REQUEST_LIST, &[1, 121, 3, 6]),
+        ]
+        .concat();
+        let payload = client_message(Ipv4Addr::UNSPECIFIED, &options);
+
+        let dhcp = DhcpObservation::parse(&payload).unwrap();
+        assert_eq!(dhcp.message_type, DhcpMessageType::Request);
+        assert_eq!(dhcp.client_mac, CLIENT);
+        assert_eq!(dhcp.client_ip, None);
+        assert_eq!(dhcp.requested_ip, Some(Ipv4Addr::new(10, 0, 0, 7)));
+        assert_eq!(dhcp.ip(), Some(Ipv4Addr::new(10, 0, 0, 7)));
+        assert_eq!(dhcp.hostname.as_deref(), Some("phone"));
+        assert_eq!(dhcp.vendor_class.as_deref(), Some("MSFT 5.0"));
+        assert_eq!(dhcp.fingerprint.as_deref(), Some("1,121,3,6"));
+    }
+
+    #[test]
+    fn release_is_parsed_with_client_address() {
+        let dhcp = DhcpObservation::parse(&release(Ipv4Addr::new(10, 0, 0, 7))).unwrap();
+        assert_eq!(dhcp.message_type, DhcpMessageType::Release);
+        assert_eq!(dhcp.client_ip, Some(Ipv4Addr::new(10, 0, 0, 7)));
+        assert_eq!(dhcp.hostname, None);
+    }
+
+    #[test]
+    fn server_and_unknown_messages_are_ignored() {
+        let mut reply = client_message(Ipv4Addr::UNSPECIFIED, &option(OPT_MESSAGE_TYPE, &[5]));
+        reply[0] = 2;
+        assert!(DhcpObservation::parse(&reply).is_none());
+
+        let unknown_type = client_message(Ipv4Addr::UNSPECIFIED, &option(OPT_MESSAGE_TYPE, &[42]));
+        assert!(DhcpObservation::parse(&unknown_type).is_none());
+
+        let no_type = client_message(Ipv4Addr::UNSPECIFIED, &option(OPT_HOSTNAME, b"a"));
+        assert!(DhcpObservation::parse(&no_type).is_none());
+    }
+
+    #[test]
+    fn malformed_messages_are_ignored() {
+        let valid = client_message(Ipv4Addr::UNSPECIFIED, &option(OPT_MESSAGE_TYPE, &[3]));
+
+        // truncated fixed part
+        for len in [0, 1, 34, OPTIONS_OFFSET - 1] {
+            assert!(DhcpObservation::parse(&valid[..len]).is_none(), "{len}");
+        }
+
+        let mut no_cookie = valid.clone();
+        no_cookie[236] = 0;
+        assert!(DhcpObservation::parse(&no_cookie).is_none());
+
+        let mut not_ethernet = valid.clone();
+        not_ethernet[1] = 6;
+        assert!(DhcpObservation::parse(&not_ethernet).is_none());
+
+        // option running past the end of the payload
+        let mut overlong = client_message(Ipv4Addr::UNSPECIFIED, &option(OPT_MESSAGE_TYPE, &[3]));
+        overlong.pop();
+        overlong.extend_from_slice(&[OPT_HOSTNAME, 10, b'a']);
+        assert!(DhcpObservation::parse(&overlong).is_none());
+
+        // length byte missing
+        let mut no_len = client_message(Ipv4Addr::UNSPECIFIED, &option(OPT_MESSAGE_TYPE, &[3]));
+        no_len.pop();
+        no_len.push(OPT_HOSTNAME);
+        assert!(DhcpObservation::parse(&no_len).is_none());
+
+        // without an end option, options stop at the end of the payload
+        let mut no_end = valid;
+        no_end.pop();
+        assert!(DhcpObservation::parse(&no_end).is_some());
+    }
+}
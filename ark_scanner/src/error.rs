@@ -1,18 +1,82 @@
-use std::{fmt::Display, io::ErrorKind};
+use std::{error::Error, fmt::Display, io, path::PathBuf, time::SystemTimeError};
 
+use crate::config::ConfigSource;
+
+/// Exit status of a configuration error (EX_CONFIG)
+pub const EXIT_CONFIG: i32 = 78;
+/// Exit status when no usable network interface is found (EX_UNAVAILABLE)
+pub const EXIT_INTERFACE: i32 = 69;
+/// Exit status when the datalink channel cannot be opened, usually for lack of root or
+/// CAP_NET_RAW (EX_NOPERM)
+pub const EXIT_CHANNEL: i32 = 77;
+/// Exit status when frames can no longer be sent or received (EX_IOERR)
+pub const EXIT_TRANSPORT: i32 = 74;
+/// Exit status when logs, device counts or captures cannot be written (EX_CANTCREAT)
+pub const EXIT_SINK: i32 = 73;
+/// Exit status when the reconnect command cannot be run (EX_OSERR)
+pub const EXIT_COMMAND: i32 = 71;
+/// Exit status when signal handlers cannot be installed (EX_SOFTWARE)
+pub const EXIT_SIGNAL: i32 = 70;
+
+/// Errors that stop the scanner, grouped by what failed
+///
+/// Each group has its own [`ArpScannerErr::exit_code`], so supervisors can tell a
+/// misconfiguration from a hardware failure.
+#[derive(Debug)]
 pub enum ArpScannerErr {
-    OpenChannelError(ErrorKind),
-    InterfaceError(InterfaceErr),
+    /// Options are missing or invalid
+    Config(ConfigErr),
+    /// No usable network interface
+    Interface(InterfaceErr),
+    /// Requested network interface cannot be used, with the reason why
+    InterfaceRejected {
+        interface: String,
+        reason: InterfaceErr,
+    },
+    /// Datalink channel could not be opened on the interface
+    Channel { interface: String, error: io::Error },
+    /// Channel of the interface was closed while scanning
+    Transport { interface: String, error: io::Error },
+    /// Logs, device counts or captured frames cannot be written
+    Sink(SinkErr),
+    /// Reconnect command cannot be run
+    Command(CommandErr),
+    /// Termination signal handlers could not be installed
+    Signal(io::Error),
+}
+
+impl ArpScannerErr {
+    /// Process exit status for the error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ArpScannerErr::Config(_) => EXIT_CONFIG,
+            ArpScannerErr::Interface(_) | ArpScannerErr::InterfaceRejected { .. } => EXIT_INTERFACE,
+            ArpScannerErr::Channel { .. } => EXIT_CHANNEL,
+            ArpScannerErr::Transport { .. } => EXIT_TRANSPORT,
+            ArpScannerErr::Sink(_) => EXIT_SINK,
+            ArpScannerErr::Command(_) => EXIT_COMMAND,
+            ArpScannerErr::Signal(_) => EXIT_SIGNAL,
+        }
+    }
 }
 
 impl Display for ArpScannerErr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let message = match self {
-            ArpScannerErr::OpenChannelError(reason) => format!(
-                "{}: {:?}",
-                "unable to open channel for network interface", &reason
-            ),
-            ArpScannerErr::InterfaceError(interface_err) => match interface_err {
+            // already prefixed
+            ArpScannerErr::Config(e) => return write!(f, "{e}"),
+            ArpScannerErr::Sink(e) => return write!(f, "{e}"),
+            ArpScannerErr::Command(e) => return write!(f, "{e}"),
+            ArpScannerErr::Channel { interface, error } => {
+                format!("unable to open channel for network interface {interface}: {error}")
+            }
+            ArpScannerErr::Transport { interface, error } => {
+                format!("channel of network interface {interface} was closed: {error}")
+            }
+            ArpScannerErr::Signal(error) => {
+                format!("unable to install signal handlers: {error}")
+            }
+            ArpScannerErr::Interface(interface_err) => match interface_err {
                 InterfaceErr::InvalidMask => {
                     String::from("chosen network interface is missing ipv4 subnet mask")
                 }
@@ -25,14 +89,54 @@ impl Display for ArpScannerErr {
                 InterfaceErr::NotFound => {
                     String::from("unable to choose default network interface")
                 }
+                reason => format!("chosen network interface {reason}"),
             },
+            ArpScannerErr::InterfaceRejected { interface, reason } => {
+                format!("network interface {interface} rejected: {reason}")
+            }
         };
         write!(f, "[arp scanner error]: {message}")
     }
 }
 
+impl Error for ArpScannerErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ArpScannerErr::Config(e) => Some(e),
+            ArpScannerErr::Sink(e) => Some(e),
+            ArpScannerErr::Command(e) => Some(e),
+            ArpScannerErr::Interface(reason) | ArpScannerErr::InterfaceRejected { reason, .. } => {
+                Some(reason)
+            }
+            ArpScannerErr::Channel { error, .. }
+            | ArpScannerErr::Transport { error, .. }
+            | ArpScannerErr::Signal(error) => Some(error),
+        }
+    }
+}
+
+impl From<ConfigErr> for ArpScannerErr {
+    fn from(e: ConfigErr) -> Self {
+        ArpScannerErr::Config(e)
+    }
+}
+
+impl From<SinkErr> for ArpScannerErr {
+    fn from(e: SinkErr) -> Self {
+        ArpScannerErr::Sink(e)
+    }
+}
+
+impl From<CommandErr> for ArpScannerErr {
+    fn from(e: CommandErr) -> Self {
+        ArpScannerErr::Command(e)
+    }
+}
+
+/// Reasons a network interface cannot be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InterfaceErr {
-    /// No default interface is found
+    /// No default interface is found, or no interface matches the requested one
     NotFound,
     /// No IPv4 mask is found for cooresponding interface
     InvalidMask,
@@ -40,4 +144,239 @@ pub enum InterfaceErr {
     NoMac,
     /// Interface has no ip address
     NoIpv4,
+    /// Interface is administratively down
+    Down,
+    /// Interface is the loopback interface
+    Loopback,
+    /// Interface cannot send broadcast frames, which ARP requests are
+    NoBroadcast,
+}
+
+impl Display for InterfaceErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            InterfaceErr::NotFound => "does not exist",
+            InterfaceErr::InvalidMask => "is missing ipv4 subnet mask",
+            InterfaceErr::NoMac => "is missing mac address",
+            InterfaceErr::NoIpv4 => "is missing ipv4 address",
+            InterfaceErr::Down => "is down",
+            InterfaceErr::Loopback => "is a loopback interface",
+            InterfaceErr::NoBroadcast => "is not broadcast capable",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+impl Error for InterfaceErr {}
+
+/// Errors loading the scanner configuration
+#[derive(Debug)]
+pub enum ConfigErr {
+    /// Config file could not be read
+    ReadFile { path: PathBuf, error: io::Error },
+    /// Config file is not valid TOML
+    ParseFile {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    /// .env file could not be loaded
+    EnvFile {
+        path: PathBuf,
+        error: dotenvy::Error,
+    },
+    /// Key is not a known option
+    UnknownKey { key: String, source: ConfigSource },
+    /// Required option is not set in any layer
+    Missing {
+        key: &'static str,
+        env: &'static str,
+    },
+    /// Value cannot be parsed as the expected type
+    Invalid {
+        key: &'static str,
+        source: ConfigSource,
+        expected: &'static str,
+        value: String,
+        reason: Option<String>,
+    },
+    /// Options that exclude each other are both set
+    Conflict {
+        key: &'static str,
+        other: &'static str,
+    },
+    /// Secret could not be read from its file
+    SecretFile { path: PathBuf, error: io::Error },
+    /// OUI database file could not be read
+    OuiDatabase { path: PathBuf, error: io::Error },
+    /// Options parsed but have errors, see [`crate::check::validate`]
+    Rejected { problems: Vec<String> },
+}
+
+impl Display for ConfigErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            ConfigErr::ReadFile { path, error } => {
+                format!("unable to read config file {}: {}", path.display(), error)
+            }
+            ConfigErr::ParseFile { path, error } => {
+                format!("invalid config file {}: {}", path.display(), error)
+            }
+            ConfigErr::EnvFile { path, error } => {
+                format!("unable to load {}: {}", path.display(), error)
+            }
+            ConfigErr::UnknownKey { key, source } => format!("unknown key {key} in {source}"),
+            ConfigErr::Missing { key, env } => format!(
+                "missing {key}, set it in the config file, in environment variable {env} \
+                 or with --set {key}=VALUE"
+            ),
+            ConfigErr::Invalid {
+                key,
+                source,
+                expected,
+                value,
+                reason,
+            } => {
+                let mut message =
+                    format!("invalid value {value:?} for {key} from {source}, expected {expected}");
+                if let Some(reason) = reason {
+                    message.push_str(&format!(" ({reason})"));
+                }
+                message
+            }
+            ConfigErr::Conflict { key, other } => {
+                format!("{key} and {other} cannot both be set")
+            }
+            ConfigErr::SecretFile { path, error } => {
+                format!("unable to read secret from {}: {}", path.display(), error)
+            }
+            ConfigErr::OuiDatabase { path, error } => {
+                format!("unable to read oui database {}: {}", path.display(), error)
+            }
+            ConfigErr::Rejected { problems } => {
+                format!("invalid options: {}", problems.join("; "))
+            }
+        };
+        write!(f, "[arp scanner error]: {message}")
+    }
+}
+
+impl Error for ConfigErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigErr::ReadFile { error, .. }
+            | ConfigErr::SecretFile { error, .. }
+            | ConfigErr::OuiDatabase { error, .. } => Some(error),
+            ConfigErr::ParseFile { error, .. } => Some(error),
+            ConfigErr::EnvFile { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Errors writing logs, device counts or captured frames
+#[derive(Debug)]
+pub enum SinkErr {
+    /// Logger could not be set up
+    Logger(fern::InitError),
+    /// Capture file could not be created
+    Capture { path: PathBuf, error: io::Error },
+    /// Capture file being replayed could not be read
+    ReadCapture(io::Error),
+    /// Device counts could not be sent to the API
+    Api { url: String, error: reqwest::Error },
+    /// System clock is before the UNIX epoch, counts cannot be timestamped
+    Clock(SystemTimeError),
+}
+
+impl Display for SinkErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            SinkErr::Logger(error) => format!("unable to set up logger: {error}"),
+            SinkErr::Capture { path, error } => {
+                format!(
+                    "unable to create capture file {}: {}",
+                    path.display(),
+                    error
+                )
+            }
+            SinkErr::ReadCapture(error) => format!("unable to read capture file: {error}"),
+            SinkErr::Api { url, error } => {
+                format!("unable to log device counts to {url}: {error}")
+            }
+            SinkErr::Clock(error) => {
+                format!("unable to timestamp device counts, system time is {error}")
+            }
+        };
+        write!(f, "[arp scanner error]: {message}")
+    }
+}
+
+impl Error for SinkErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SinkErr::Logger(error) => Some(error),
+            SinkErr::Capture { error, .. } | SinkErr::ReadCapture(error) => Some(error),
+            SinkErr::Api { error, .. } => Some(error),
+            SinkErr::Clock(error) => Some(error),
+        }
+    }
+}
+
+/// Errors running the reconnect command
+#[derive(Debug)]
+pub enum CommandErr {
+    /// Command has no program to run
+    Empty,
+    /// Program could not be started
+    Spawn { cmd: String, error: io::Error },
+}
+
+impl Display for CommandErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            CommandErr::Empty => String::from("reconnect command is empty"),
+            CommandErr::Spawn { cmd, error } => {
+                format!("unable to run reconnect command {cmd}: {error}")
+            }
+        };
+        write!(f, "[arp scanner error]: {message}")
+    }
+}
+
+impl Error for CommandErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommandErr::Empty => None,
+            CommandErr::Spawn { error, .. } => Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NetworkCommand;
+
+    #[test]
+    fn wrapped_errors_are_the_source() {
+        let spawn = NetworkCommand::new("/nonexistent/reconnect --now")
+            .unwrap()
+            .run()
+            .unwrap_err();
+        assert!(matches!(&spawn, CommandErr::Spawn { cmd, .. } if cmd == "/nonexistent/reconnect"));
+
+        let e = ArpScannerErr::from(spawn);
+        assert_eq!(e.exit_code(), EXIT_COMMAND);
+
+        // the command error, then the io error it wraps
+        let source = e.source().unwrap();
+        assert_eq!(source.to_string(), e.to_string());
+        assert!(source.source().unwrap().is::<io::Error>());
+
+        let e = ArpScannerErr::from(ConfigErr::Conflict {
+            key: "sinks.api_key",
+            other: "sinks.api_key_file",
+        });
+        assert!(e.source().unwrap().is::<ConfigErr>());
+    }
 }
@@ -0,0 +1,69 @@
+//! ARP based device presence scanner.
+//!
+//! The scanner periodically sends ARP requests to every address in the subnet of a
+//! network interface, records the mac address of every host that answers in a [`MacCache`]
+//! and logs the number of active devices, either locally or to a remote API.
+//!
+//! The quickest way to embed the scanner is [`init_arp_scanner`], which runs the full
+//! pipeline on the default interface with the given [`ScannerOptions`] until SIGINT or SIGTERM.
+//! [`start_arp_scanner`] runs the same pipeline in the background and returns a
+//! [`ScannerHandle`] to read its device counts and stop it. When several interfaces are
+//! configured, e.g. VLAN sub-interfaces of one gateway, each gets its own pipeline and device
+//! count.
+//!
+//! Frames are sent and received through a [`PacketTransport`]. Besides the pnet backed
+//! [`PnetTransport`], [`simulation::SimulatedLan`] runs the pipeline against an in-memory
+//! network without root privileges. Captures taken with tcpdump can be pushed through the same
+//! packet handling with [`replay::replay_capture`]. [`check::check_options`] validates options and
+//! resolves the interfaces and targets they select without opening a channel.
+//!
+//! Failures are reported as [`ArpScannerErr`], grouped by what failed (config, interface,
+//! channel, transport, sink or command) with a process exit status for each group.
+//!
+//! The building blocks ([`gen_arp_request`], [`compute_subnet_ips`], [`MacCache`],
+//! [`CacheLogger`]) are exported for tools that want to assemble their own pipeline.
+
+pub mod announce;
+pub mod cache;
+pub mod cache_logger;
+pub mod capture;
+pub mod chatter;
+pub mod check;
+pub mod classify;
+pub mod config;
+pub mod dhcp;
+pub mod error;
+pub mod ndp;
+pub mod network;
+pub mod oui;
+pub mod pacing;
+pub mod presence;
+pub mod privacy;
+pub mod randomized;
+pub mod registry;
+pub mod reload;
+pub mod replay;
+pub mod reprobe;
+pub mod scanner;
+pub mod secret;
+pub mod shutdown;
+pub mod simulation;
+mod sync;
+pub mod targets;
+pub mod transport;
+
+pub use cache::MacCache;
+pub use cache_logger::{CacheLogger, Logger};
+pub use config::{load_scanner_opts, ConfigLayers, ScannerOptions};
+pub use error::{ArpScannerErr, CommandErr, ConfigErr, InterfaceErr, SinkErr};
+pub use network::{
+    compute_subnet_ips, gen_arp_request, gen_unicast_arp_request, select_default_interface,
+    validate_interface, InterfaceSelector,
+};
+pub use scanner::{
+    init_arp_scanner, init_arp_scanner_with_reload, start_arp_scanner,
+    start_arp_scanner_with_interfaces, start_arp_scanner_with_transport, ScannedInterface,
+    ScannerHandle,
+};
+pub use secret::Secret;
+pub use transport::{PacketTransport, PnetTransport};
@@ -1,43 +1,313 @@
-use std::{env, fs, io};
+use std::{fs, io, path::Path, path::PathBuf, process};
 
-use config::load_scanner_opts;
+use ark_scanner::{
+    capture::CaptureReader,
+    check::{check_options, effective_options},
+    init_arp_scanner_with_reload, load_scanner_opts,
+    reload::Reloader,
+    replay::{replay_capture, ReplayOptions},
+    ArpScannerErr, ConfigErr, ConfigLayers, ScannerOptions, SinkErr,
+};
+use clap::{Args, Parser, Subcommand};
+use ipnetwork::Ipv4Network;
 use log::log;
+use pnet_datalink::MacAddr;
 
-mod cache;
-mod cache_logger;
-mod config;
-mod error;
-mod network;
-mod scanner;
+const DEFAULT_ENV_PATH: &str = "./.scanner.dev.env";
+
+const CONFIG_HELP: &str = "Options are taken from, in order of precedence: command line flags, \
+environment variables, the .env file, the TOML config file and defaults. See \
+scanner.example.toml for the config file keys and .env.example for the environment variables \
+overriding them. A running scanner reloads its options on SIGHUP.
+
+Exit status: 1 if check finds errors, 78 for configuration errors, 69 if no usable interface \
+is found, 77 if the channel cannot be opened (root or CAP_NET_RAW is needed), 74 if a channel \
+is closed while scanning, 73 if logs or captures cannot be written or read, 71 if the reconnect \
+command is invalid and 70 if signal handlers cannot be installed.";
+
+/// ARP based device presence scanner
+#[derive(Parser)]
+#[command(version, after_help = CONFIG_HELP)]
+struct Cli {
+    /// .env file read below the environment, defaults to ./.scanner.dev.env if it exists
+    env_path: Option<PathBuf>,
+    /// Also reloads the options when the config, .env or API key file is modified
+    #[arg(long, global = true)]
+    watch: bool,
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Args, Clone)]
+struct ConfigArgs {
+    /// TOML config file
+    #[arg(short, long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// .env file read below the environment
+    #[arg(long, global = true, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+    /// Overrides a config file key, e.g. --set timing.arp_scan_period_secs=2
+    #[arg(short, long, global = true, value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    set: Vec<(String, String)>,
+    /// Location device counts are logged as
+    #[arg(long, global = true)]
+    location: Option<String>,
+    /// Scan mode, active or passive
+    #[arg(long, global = true)]
+    mode: Option<String>,
+    /// Interface to scan as selector[:location], can be repeated
+    #[arg(long = "interface", global = true, value_name = "SELECTOR")]
+    interfaces: Vec<String>,
+    /// Logs trace level information
+    #[arg(long, global = true)]
+    trace: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Replays a pcap or pcapng capture and prints the device count time series as csv
+    Replay {
+        /// Capture file
+        capture: PathBuf,
+        /// .env file read below the environment
+        env_path: Option<PathBuf>,
+        /// Waits between packets to match the capture timing
+        #[arg(long)]
+        realtime: bool,
+        /// Mac address of the machine the capture was taken on, its frames are not counted
+        #[arg(long, value_name = "MAC")]
+        own_mac: Option<MacAddr>,
+        /// Subnet the capture was taken in, devices outside of it are not counted
+        #[arg(long, value_name = "CIDR")]
+        subnet: Option<Ipv4Network>,
+    },
+    /// Checks the configuration and the interfaces and targets it selects, without scanning
+    ///
+    /// Prints the effective configuration with secrets redacted and exits with status 1 if
+    /// there are errors.
+    Check {
+        /// .env file read below the environment
+        env_path: Option<PathBuf>,
+    },
+}
+
+enum Mode {
+    Scan,
+    Replay {
+        capture: PathBuf,
+        options: ReplayOptions,
+    },
+    Check,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let path = match args.get(1) {
-        Some(p) => p,
-        None => {
-            println!("did not specify .env path, defaulting to ./.scanner.dev.env");
-            "./.scanner.dev.env"
-        }
+    let cli = Cli::parse();
+
+    let (scanner_options, mode, env_file) = match load_config(&cli) {
+        Ok(loaded) => loaded,
+        Err(e) => exit(ArpScannerErr::Config(e)),
     };
-    if let Err(e) = dotenvy::from_path(path) {
-        panic!("unable to load {path}, {e}")
+
+    // nothing is scanned or logged, and the log file of a running scanner is kept
+    if let Mode::Check = mode {
+        process::exit(check(&scanner_options));
     }
 
-    let scanner_options = load_scanner_opts();
+    // replayed samples are written to stdout, keep it free of log lines
+    let log_to_stdout = matches!(mode, Mode::Scan);
 
-    if let Err(e) = init_logger(scanner_options.trace) {
-        panic!("{}", e);
+    if let Err(e) = init_logger(scanner_options.trace, log_to_stdout) {
+        exit(SinkErr::Logger(e).into());
     }
 
-    match scanner::init_arp_scanner(scanner_options) {
-        Err(e) => log!(log::Level::Error, "{}", e),
-        _ => {
-            log!(log::Level::Info, "exiting scanner...");
+    match mode {
+        Mode::Scan => match init_arp_scanner_with_reload(
+            scanner_options.clone(),
+            reloader(&cli, env_file, &scanner_options),
+        ) {
+            Err(e) => {
+                log!(log::Level::Error, "{}", e);
+                process::exit(e.exit_code());
+            }
+            _ => {
+                log!(log::Level::Info, "exiting scanner...");
+            }
+        },
+        Mode::Replay { capture, options } => {
+            if let Err(e) = replay(&capture, &scanner_options, &options) {
+                log!(log::Level::Error, "{} (replaying {})", e, capture.display());
+                process::exit(e.exit_code());
+            }
         }
+        Mode::Check => {}
     }
 }
 
-pub fn init_logger(trace: bool) -> Result<(), fern::InitError> {
+// Exits with the status of an error raised before the logger is set up
+fn exit(e: ArpScannerErr) -> ! {
+    eprintln!("{e}");
+    process::exit(e.exit_code())
+}
+
+/// Loads the .env file and config layers, and what to run with them
+fn load_config(cli: &Cli) -> Result<(ScannerOptions, Mode, Option<PathBuf>), ConfigErr> {
+    let (env_path, mode) = match &cli.command {
+        None => (cli.env_path.as_ref(), Mode::Scan),
+        Some(Command::Replay {
+            capture,
+            env_path,
+            realtime,
+            own_mac,
+            subnet,
+        }) => (
+            env_path.as_ref(),
+            Mode::Replay {
+                capture: capture.clone(),
+                options: ReplayOptions {
+                    realtime: *realtime,
+                    own_mac: *own_mac,
+                    subnet: *subnet,
+                },
+            },
+        ),
+        Some(Command::Check { env_path }) => (env_path.as_ref(), Mode::Check),
+    };
+
+    let args = &cli.config;
+    let env_file = match args.env_file.as_ref().or(env_path) {
+        Some(path) => Some(path.clone()),
+        // without a config file the options are most likely in the default .env file
+        None if Path::new(DEFAULT_ENV_PATH).exists() => Some(PathBuf::from(DEFAULT_ENV_PATH)),
+        None if args.config.is_none() => {
+            eprintln!("no config file or .env path given and {DEFAULT_ENV_PATH} does not exist");
+            None
+        }
+        None => None,
+    };
+
+    Ok((load_options(args, env_file.as_deref())?, mode, env_file))
+}
+
+/// Reloads the .env file and config layers on SIGHUP, and on changes of the files if watched
+fn reloader(cli: &Cli, env_file: Option<PathBuf>, options: &ScannerOptions) -> Reloader {
+    let mut watched: Vec<PathBuf> = cli.config.config.iter().cloned().collect();
+    watched.extend(env_file.clone());
+    // a rotated secret is replaced by a new file, which changes the modification time
+    watched.extend(options.log_api_key_file.iter().map(PathBuf::from));
+
+    let args = cli.config.clone();
+    let mut reloader = Reloader::new(move || load_options(&args, env_file.as_deref()));
+
+    if cli.watch {
+        for path in watched {
+            reloader = reloader.watch(path);
+        }
+    }
+
+    reloader
+}
+
+// The .env file is read into its own layer, the process environment is never changed
+fn load_options(args: &ConfigArgs, env_file: Option<&Path>) -> Result<ScannerOptions, ConfigErr> {
+    let mut layers = match &args.config {
+        Some(path) => ConfigLayers::new().with_file(path)?,
+        None => ConfigLayers::new(),
+    };
+    if let Some(path) = env_file {
+        layers = layers.with_env_file(path)?;
+    }
+
+    if let Some(location) = &args.location {
+        layers = layers.with_override("location", location, "--location")?;
+    }
+    if let Some(mode) = &args.mode {
+        layers = layers.with_override("mode", mode, "--mode")?;
+    }
+    if !args.interfaces.is_empty() {
+        layers = layers.with_override("interfaces", &args.interfaces.join(","), "--interface")?;
+    }
+    if args.trace {
+        layers = layers.with_override("trace", "true", "--trace")?;
+    }
+    for (key, value) in &args.set {
+        layers = layers.with_override(key, value, "--set")?;
+    }
+
+    load_scanner_opts(&layers)
+}
+
+fn parse_assignment(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) => Ok((String::from(key.trim()), String::from(value))),
+        None => Err(format!("expected KEY=VALUE, got {s}")),
+    }
+}
+
+// Prints the effective configuration, selected interfaces and problems, returns the exit status
+fn check(options: &ScannerOptions) -> i32 {
+    let report = check_options(options, &pnet_datalink::interfaces());
+
+    println!("effective configuration:");
+    for (key, value) in effective_options(options) {
+        println!("    {key} = {value}");
+    }
+
+    println!("interfaces:");
+    for interface in &report.interfaces {
+        let targets = match (interface.targets.first(), interface.targets.last()) {
+            (Some(first), Some(last)) => format!("{first}-{last}"),
+            _ => String::from("none"),
+        };
+
+        println!(
+            "    {} at {}: subnet {}, {} targets ({})",
+            interface.name,
+            interface.location,
+            interface.subnet.map_or_else(
+                || String::from("none"),
+                |subnet| format!("{}/{}", subnet.network(), subnet.prefix())
+            ),
+            interface.targets.len(),
+            targets
+        );
+    }
+
+    if report.problems.is_empty() {
+        println!("no problems found");
+    }
+    for problem in &report.problems {
+        println!("{problem}");
+    }
+
+    if report.has_errors() {
+        1
+    } else {
+        0
+    }
+}
+
+// Prints the replayed time series as csv: created_at,location,device_count
+fn replay(
+    capture: &Path,
+    options: &ScannerOptions,
+    replay: &ReplayOptions,
+) -> Result<(), ArpScannerErr> {
+    let reader = CaptureReader::open(capture).map_err(SinkErr::ReadCapture)?;
+
+    println!("created_at,location,device_count");
+    replay_capture(reader, options, replay, |sample| {
+        println!(
+            "{},{},{}",
+            sample.timestamp.as_secs(),
+            options.location,
+            sample.device_count
+        );
+    })
+}
+
+fn init_logger(trace: bool, log_to_stdout: bool) -> Result<(), fern::InitError> {
     let _ = fs::remove_file("scanner.log");
 
     let mut dispatch = fern::Dispatch::new()
@@ -58,7 +328,11 @@ pub fn init_logger(trace: bool) -> Result<(), fern::InitError> {
         dispatch = dispatch.level(log::LevelFilter::Debug)
     }
 
-    dispatch = dispatch.chain(io::stdout());
+    if log_to_stdout {
+        dispatch = dispatch.chain(io::stdout());
+    } else {
+        dispatch = dispatch.chain(io::stderr());
+    }
 
     dispatch.apply()?;
 
@@ -0,0 +1,261 @@
+//   Info on neighbor discovery:
+// - https://www.rfc-editor.org/rfc/rfc4861
+// - https://www.rfc-editor.org/rfc/rfc4291#section-2.7.1 (solicited-node multicast)
+
+use std::net::Ipv6Addr;
+
+use pnet::packet::{
+    ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+    icmpv6::{self, Icmpv6Packet, Icmpv6Types},
+    ip::IpNextHeaderProtocols,
+    ipv6::{Ipv6Packet, MutableIpv6Packet},
+    Packet,
+};
+use pnet_datalink::MacAddr;
+
+use crate::presence::ArpKind;
+
+/// Every IPv6 node on the link
+pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+const ETHERNET_HEADER_SIZE: usize = 14;
+const IPV6_HEADER_SIZE: usize = 40;
+// NDP messages are dropped by receivers unless sent with the maximum hop limit
+const NDP_HOP_LIMIT: u8 = 255;
+
+const NDP_OPT_SOURCE_LL_ADDR: u8 = 1;
+const NDP_OPT_TARGET_LL_ADDR: u8 = 2;
+
+/// Neighbor advertisement flag set when answering a solicitation
+pub const NA_FLAG_SOLICITED: u8 = 0b0100_0000;
+/// Neighbor advertisement flag asking receivers to update their cache
+pub const NA_FLAG_OVERRIDE: u8 = 0b0010_0000;
+
+/// Address whose group a node joins for each of its unicast addresses
+pub fn solicited_node_multicast(ip: Ipv6Addr) -> Ipv6Addr {
+    let o = ip.octets();
+    Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | o[13] as u16,
+        ((o[14] as u16) << 8) | o[15] as u16,
+    )
+}
+
+/// Ethernet address an IPv6 multicast group is delivered to
+pub fn multicast_mac(ip: Ipv6Addr) -> MacAddr {
+    let o = ip.octets();
+    MacAddr::new(0x33, 0x33, o[12], o[13], o[14], o[15])
+}
+
+/// Generates a neighbor solicitation for target_ip, sent to its solicited-node multicast group
+pub fn gen_neighbor_solicitation(
+    source_mac: MacAddr,
+    source_ip: Ipv6Addr,
+    target_ip: Ipv6Addr,
+) -> Vec<u8> {
+    let mut message = vec![135, 0, 0, 0, 0, 0, 0, 0];
+    message.extend_from_slice(&target_ip.octets());
+    push_ll_addr_option(&mut message, NDP_OPT_SOURCE_LL_ADDR, source_mac);
+
+    let destination = solicited_node_multicast(target_ip);
+    gen_icmpv6_frame(
+        source_mac,
+        multicast_mac(destination),
+        source_ip,
+        destination,
+        message,
+    )
+}
+
+/// Generates an echo request to all nodes, which every IPv6 host on the link answers
+pub fn gen_all_nodes_echo_request(source_mac: MacAddr, source_ip: Ipv6Addr) -> Vec<u8> {
+    // type, code, checksum, identifier, sequence number
+    let message = vec![128, 0, 0, 0, 0x61, 0x72, 0, 1];
+
+    gen_icmpv6_frame(
+        source_mac,
+        multicast_mac(ALL_NODES),
+        source_ip,
+        ALL_NODES,
+        message,
+    )
+}
+
+/// Generates a neighbor advertisement of target_ip, as a host answering a solicitation would
+pub fn gen_neighbor_advertisement(
+    source_mac: MacAddr,
+    target_ip: Ipv6Addr,
+    destination_mac: MacAddr,
+    destination_ip: Ipv6Addr,
+    flags: u8,
+) -> Vec<u8> {
+    let mut message = vec![136, 0, 0, 0, flags, 0, 0, 0];
+    message.extend_from_slice(&target_ip.octets());
+    push_ll_addr_option(&mut message, NDP_OPT_TARGET_LL_ADDR, source_mac);
+
+    gen_icmpv6_frame(
+        source_mac,
+        destination_mac,
+        target_ip,
+        destination_ip,
+        message,
+    )
+}
+
+/// Wraps an ICMPv6 message in IPv6 and Ethernet headers, filling in its checksum
+pub fn gen_icmpv6_frame(
+    source_mac: MacAddr,
+    destination_mac: MacAddr,
+    source_ip: Ipv6Addr,
+    destination_ip: Ipv6Addr,
+    mut message: Vec<u8>,
+) -> Vec<u8> {
+    let checksum = icmpv6::checksum(
+        &Icmpv6Packet::new(&message).unwrap(),
+        &source_ip,
+        &destination_ip,
+    );
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    let mut ip_buf = vec![0u8; IPV6_HEADER_SIZE + message.len()];
+    let mut ip_packet = MutableIpv6Packet::new(&mut ip_buf).unwrap();
+
+    ip_packet.set_version(6);
+    ip_packet.set_payload_length(message.len() as u16);
+    ip_packet.set_next_header(IpNextHeaderProtocols::Icmpv6);
+    ip_packet.set_hop_limit(NDP_HOP_LIMIT);
+    ip_packet.set_source(source_ip);
+    ip_packet.set_destination(destination_ip);
+    ip_packet.set_payload(&message);
+
+    let mut eth_buf = vec![0u8; ETHERNET_HEADER_SIZE + ip_buf.len()];
+    let mut eth_packet = MutableEthernetPacket::new(&mut eth_buf).unwrap();
+
+    eth_packet.set_destination(destination_mac);
+    eth_packet.set_source(source_mac);
+    eth_packet.set_ethertype(EtherTypes::Ipv6);
+    eth_packet.set_payload(&ip_buf);
+
+    eth_buf
+}
+
+fn push_ll_addr_option(message: &mut Vec<u8>, option_type: u8, mac: MacAddr) {
+    // length is in units of 8 bytes
+    message.extend_from_slice(&[option_type, 1]);
+    message.extend_from_slice(&mac.octets());
+}
+
+/// Neighbor discovery (or echo reply) message parsed from an Ethernet frame
+///
+/// Messages are classified with the same kinds as ARP packets: solicited advertisements and
+/// echo replies are replies, unsolicited advertisements and router solicitations are
+/// announcements, solicitations from the unspecified address are duplicate address probes.
+pub struct NdpObservation {
+    pub kind: ArpKind,
+    /// Source mac of the Ethernet frame
+    pub eth_source: MacAddr,
+    /// Link-layer address carried in the message options, if any
+    pub link_layer_addr: Option<MacAddr>,
+    pub source_ip: Ipv6Addr,
+    /// Address a solicitation or advertisement is about
+    pub target_ip: Option<Ipv6Addr>,
+}
+
+impl NdpObservation {
+    /// Parses an ICMPv6 neighbor discovery or echo reply frame, returns None for any other frame
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let eth_packet = EthernetPacket::new(frame)?;
+
+        if eth_packet.get_ethertype().0 != EtherTypes::Ipv6.0 {
+            return None;
+        }
+
+        let ip_packet = Ipv6Packet::new(eth_packet.payload())?;
+
+        if ip_packet.get_next_header() != IpNextHeaderProtocols::Icmpv6 {
+            return None;
+        }
+
+        let message = ip_packet.payload();
+        let icmp_type = Icmpv6Packet::new(message)?.get_icmpv6_type();
+        let source_ip = ip_packet.get_source();
+
+        let (kind, target_ip, options) = if icmp_type == Icmpv6Types::EchoReply {
+            (ArpKind::Reply, None, &[][..])
+        } else {
+            // forged NDP from off-link is rejected by hosts, do the same
+            if ip_packet.get_hop_limit() != NDP_HOP_LIMIT {
+                return None;
+            }
+
+            if icmp_type == Icmpv6Types::RouterSolicit {
+                (ArpKind::Gratuitous, None, message.get(8..)?)
+            } else if icmp_type == Icmpv6Types::NeighborSolicit {
+                let target = ipv6_at(message, 8)?;
+                let kind = if source_ip.is_unspecified() {
+                    ArpKind::Probe
+                } else {
+                    ArpKind::Request
+                };
+                (kind, Some(target), message.get(24..)?)
+            } else if icmp_type == Icmpv6Types::NeighborAdvert {
+                let target = ipv6_at(message, 8)?;
+                let kind = if message.get(4)? & NA_FLAG_SOLICITED != 0 {
+                    ArpKind::Reply
+                } else {
+                    ArpKind::Gratuitous
+                };
+                (kind, Some(target), message.get(24..)?)
+            } else {
+                return None;
+            }
+        };
+
+        Some(Self {
+            kind,
+            eth_source: eth_packet.get_source(),
+            link_layer_addr: find_ll_addr_option(options),
+            source_ip,
+            target_ip,
+        })
+    }
+
+    /// Address the sender is present at
+    /// Advertisements and probes are about their target, other messages about their source.
+    pub fn claimed_ip(&self) -> Ipv6Addr {
+        match (self.kind, self.target_ip) {
+            (ArpKind::Reply | ArpKind::Gratuitous | ArpKind::Probe, Some(target)) => target,
+            _ => self.source_ip,
+        }
+    }
+}
+
+fn ipv6_at(message: &[u8], offset: usize) -> Option<Ipv6Addr> {
+    let bytes: [u8; 16] = message.get(offset..offset + 16)?.try_into().ok()?;
+    Some(Ipv6Addr::from(bytes))
+}
+
+fn find_ll_addr_option(mut options: &[u8]) -> Option<MacAddr> {
+    while options.len() >= 8 {
+        let option_type = options[0];
+        let len = options[1] as usize * 8;
+
+        if len == 0 || options.len() < len {
+            return None;
+        }
+
+        if option_type == NDP_OPT_SOURCE_LL_ADDR || option_type == NDP_OPT_TARGET_LL_ADDR {
+            let o = &options[2..8];
+            return Some(MacAddr::new(o[0], o[1], o[2], o[3], o[4], o[5]));
+        }
+
+        options = &options[len..];
+    }
+
+    None
+}
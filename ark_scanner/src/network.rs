@@ -3,11 +3,15 @@
 // - https://www.sciencedirect.com/topics/computer-science/address-resolution-protocol-request#:~:text=ARP%20Packets,same%20way%20as%20IP%20packets
 
 use std::{
-    net::Ipv4Addr,
-    process::Command,
-    sync::{Arc, RwLock},
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    process::{Command, ExitStatus},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+use ipnetwork::IpNetwork;
+
 use log::log;
 use pnet::{
     packet::{
@@ -19,19 +23,40 @@ use pnet::{
 };
 use pnet_datalink::NetworkInterface;
 
+use crate::error::{CommandErr, InterfaceErr};
+
 const ARP_PACKET_SIZE: usize = 28;
 const ETHERNET_HW_ADDR_LEN: u8 = 6;
 const IPV4_ADDR_LEN: u8 = 4;
-const ETHERNET_FRAME_SIZE: usize = 42; // ARP_PACKET_SIZE + 14 for the ethernet header
+/// Size of an ARP request wrapped in an Ethernet frame
+pub const ETHERNET_FRAME_SIZE: usize = 42; // ARP_PACKET_SIZE + 14 for the ethernet header
 
-// Generates ARP message wrapped in an Ethernet frame
+/// Generates ARP message wrapped in an Ethernet frame
 pub fn gen_arp_request(
     source_mac: MacAddr,
     source_ip: Ipv4Addr,
     target_ip: Ipv4Addr,
 ) -> Option<[u8; ETHERNET_FRAME_SIZE]> {
-    let target_mac = MacAddr::broadcast();
+    gen_arp_frame(source_mac, source_ip, MacAddr::broadcast(), target_ip)
+}
+
+/// Generates ARP message sent only to target_mac, like arping does,
+/// so checking a known host does not wake every host on the segment
+pub fn gen_unicast_arp_request(
+    source_mac: MacAddr,
+    source_ip: Ipv4Addr,
+    target_mac: MacAddr,
+    target_ip: Ipv4Addr,
+) -> Option<[u8; ETHERNET_FRAME_SIZE]> {
+    gen_arp_frame(source_mac, source_ip, target_mac, target_ip)
+}
 
+fn gen_arp_frame(
+    source_mac: MacAddr,
+    source_ip: Ipv4Addr,
+    target_mac: MacAddr,
+    target_ip: Ipv4Addr,
+) -> Option<[u8; ETHERNET_FRAME_SIZE]> {
     let mut arp_buf = [0u8; ARP_PACKET_SIZE];
     let mut arp_packet = MutableArpPacket::new(&mut arp_buf).unwrap();
 
@@ -60,20 +85,109 @@ pub fn gen_arp_request(
     eth_packet.packet().try_into().ok()
 }
 
+/// Picks the first interface that is up, broadcast capable, not loopback
+/// and has both an ipv4 and a mac address
 pub fn select_default_interface(interfaces: &[NetworkInterface]) -> Option<NetworkInterface> {
     interfaces
         .iter()
-        .find(|interface| {
-            interface.is_up()
-                && interface.is_broadcast()
-                && !interface.is_loopback()
-                && !interface.ips.is_empty()
-                && interface.ips.iter().any(|ip| ip.is_ipv4())
-                && interface.mac.is_some()
-        })
+        .find(|interface| validate_interface(interface, false).is_ok())
         .cloned()
 }
 
+/// Checks that interface can be scanned, returning the first reason it cannot
+/// An interface without ipv4 is accepted if ipv6_only is set and it has an ipv6 address.
+pub fn validate_interface(
+    interface: &NetworkInterface,
+    ipv6_only: bool,
+) -> Result<(), InterfaceErr> {
+    if interface.is_loopback() {
+        return Err(InterfaceErr::Loopback);
+    }
+
+    if !interface.is_up() {
+        return Err(InterfaceErr::Down);
+    }
+
+    if !interface.is_broadcast() {
+        return Err(InterfaceErr::NoBroadcast);
+    }
+
+    match interface.mac {
+        Some(mac) if mac != MacAddr::zero() => {}
+        _ => return Err(InterfaceErr::NoMac),
+    }
+
+    let has_ipv4 = interface.ips.iter().any(|ip| ip.is_ipv4());
+    let has_ipv6 = interface.ips.iter().any(|ip| ip.is_ipv6());
+
+    if !(has_ipv4 || (ipv6_only && has_ipv6)) {
+        return Err(InterfaceErr::NoIpv4);
+    }
+
+    Ok(())
+}
+
+/// Identifies a requested interface by its name, mac address or one of its ip addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterfaceSelector {
+    Name(String),
+    Mac(MacAddr),
+    /// Matches interfaces with an address inside the network, a single ip matches exactly
+    Network(IpNetwork),
+}
+
+impl InterfaceSelector {
+    pub fn matches(&self, interface: &NetworkInterface) -> bool {
+        match self {
+            InterfaceSelector::Name(name) => interface.name == *name,
+            InterfaceSelector::Mac(mac) => interface.mac == Some(*mac),
+            InterfaceSelector::Network(network) => {
+                interface.ips.iter().any(|ip| contains(network, ip.ip()))
+            }
+        }
+    }
+}
+
+fn contains(network: &IpNetwork, ip: IpAddr) -> bool {
+    match (network, ip) {
+        (IpNetwork::V4(network), IpAddr::V4(ip)) => network.contains(ip),
+        (IpNetwork::V6(network), IpAddr::V6(ip)) => network.contains(ip),
+        _ => false,
+    }
+}
+
+impl FromStr for InterfaceSelector {
+    type Err = String;
+
+    /// Parses a mac address, an ip or cidr, anything else is taken as an interface name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(String::from("empty interface selector"));
+        }
+
+        if let Ok(mac) = s.parse::<MacAddr>() {
+            return Ok(InterfaceSelector::Mac(mac));
+        }
+
+        if let Ok(network) = s.parse::<IpNetwork>() {
+            return Ok(InterfaceSelector::Network(network));
+        }
+
+        Ok(InterfaceSelector::Name(String::from(s)))
+    }
+}
+
+impl Display for InterfaceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterfaceSelector::Name(name) => write!(f, "{name}"),
+            InterfaceSelector::Mac(mac) => write!(f, "{mac}"),
+            InterfaceSelector::Network(network) => write!(f, "{network}"),
+        }
+    }
+}
+
+/// Whether the interface still exists and is running
 pub fn is_interface_connected(interface: &NetworkInterface) -> bool {
     let interfaces = pnet_datalink::interfaces();
 
@@ -83,7 +197,8 @@ pub fn is_interface_connected(interface: &NetworkInterface) -> bool {
     }
 }
 
-// Returns all IPs that fall within the same subnet as sample_ip
+/// Returns all IPs that fall within the same subnet as sample_ip,
+/// excluding the network and broadcast addresses
 // https://github.com/google/gopacket/blob/3aa782ce48d4a525acaebab344cedabfb561f870/examples/arpscan/arpscan.go
 pub fn compute_subnet_ips(sample_up: Ipv4Addr, subnet_mask: Ipv4Addr) -> Vec<Ipv4Addr> {
     let network = sample_up.octets();
@@ -118,65 +233,65 @@ pub fn compute_subnet_ips(sample_up: Ipv4Addr, subnet_mask: Ipv4Addr) -> Vec<Ipv
     out
 }
 
-// Disallows parallel running of command
+/// Disallows parallel running of command
 pub struct NetworkCommandLimiter {
     cmd: NetworkCommand,
-    is_running: Arc<RwLock<bool>>,
+    is_running: AtomicBool,
 }
 
 impl NetworkCommandLimiter {
-    pub fn new(cmd: &String) -> Self {
-        Self {
-            cmd: NetworkCommand::new(cmd),
-            is_running: Arc::new(RwLock::new(false)),
-        }
+    pub fn new(cmd: &str) -> Result<Self, CommandErr> {
+        Ok(Self {
+            cmd: NetworkCommand::new(cmd)?,
+            is_running: AtomicBool::new(false),
+        })
     }
 
+    /// Runs the command unless it is already running on another thread
     pub fn run(&self) {
-        let running_rd = self.is_running.read().unwrap();
-
-        if !*running_rd {
-            drop(running_rd); // drop so write lock can be obtained
-
-            let mut running_w = self.is_running.write().unwrap();
-            *running_w = true;
-            drop(running_w); // drop so other threads can access read lock
-
-            self.cmd.run();
-
-            let mut running_w = self.is_running.write().unwrap();
-            *running_w = false;
+        if self
+            .is_running
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            match self.cmd.run() {
+                Ok(status) => log!(log::Level::Info, "Reconnect status: {}", status),
+                Err(e) => log!(log::Level::Error, "{}", e),
+            }
+            self.is_running.store(false, Ordering::Release);
         }
     }
 }
 
-// A reusable command runner
-// Generates new command on each run
+/// A reusable command runner
+/// Generates new command on each run
 pub struct NetworkCommand {
     cmd: String,
     args: Vec<String>,
 }
 
 impl NetworkCommand {
-    pub fn new(cmd: &String) -> Self {
-        let ar: Vec<&str> = cmd.split(' ').collect();
-
-        if ar.is_empty() {
-            panic!("invalid command: {cmd}");
-        }
-
-        let ar_str: Vec<String> = ar.into_iter().map(String::from).collect();
-
-        Self {
-            cmd: String::from(&ar_str[0]),
-            args: ar_str[1..ar_str.len()].to_vec(),
+    /// Splits cmd on whitespace into program and arguments
+    pub fn new(cmd: &str) -> Result<Self, CommandErr> {
+        let mut ar = cmd.split_whitespace().map(String::from);
+
+        match ar.next() {
+            Some(program) => Ok(Self {
+                cmd: program,
+                args: ar.collect(),
+            }),
+            None => Err(CommandErr::Empty),
         }
     }
 
-    pub fn run(&self) {
-        match Command::new(&self.cmd).args(&self.args).status() {
-            Ok(s) => log!(log::Level::Info, "Reconnect status: {}", s.to_string()),
-            Err(e) => log!(log::Level::Error, "{}", e.to_string()),
-        }
+    /// Runs the command to completion, returning its exit status
+    pub fn run(&self) -> Result<ExitStatus, CommandErr> {
+        Command::new(&self.cmd)
+            .args(&self.args)
+            .status()
+            .map_err(|error| CommandErr::Spawn {
+                cmd: self.cmd.clone(),
+                error,
+            })
     }
 }
@@ -0,0 +1,179 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use pnet_datalink::MacAddr;
+
+// Common vendors, in the IEEE MA-L csv format
+const EMBEDDED_DATABASE: &str = include_str!("../data/oui.csv");
+
+/// Vendors of mac address blocks (OUIs) assigned by the IEEE
+///
+/// A small database of common vendors is embedded, a full one can be loaded from
+/// https://standards-oui.ieee.org/oui/oui.csv or oui.txt. Only MA-L (24 bit) assignments are used.
+pub struct OuiDatabase {
+    vendors: HashMap<[u8; 3], String>,
+}
+
+impl OuiDatabase {
+    /// Database of common vendors shipped with the scanner
+    pub fn embedded() -> Self {
+        Self::parse(EMBEDDED_DATABASE)
+    }
+
+    /// Loads the embedded database, with entries of the IEEE file at path taking precedence
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut database = Self::embedded();
+        database
+            .vendors
+            .extend(Self::parse(&fs::read_to_string(path)?).vendors);
+        Ok(database)
+    }
+
+    /// Parses an IEEE oui.csv or oui.txt file, lines that are neither are skipped
+    pub fn parse(contents: &str) -> Self {
+        let vendors = contents
+            .lines()
+            .filter_map(|line| parse_csv_line(line).or_else(|| parse_txt_line(line)))
+            .collect();
+
+        Self { vendors }
+    }
+
+    /// Vendor of mac, None for unknown and locally administered (e.g. randomized) addresses
+    pub fn lookup(&self, mac: &MacAddr) -> Option<&str> {
+        if is_locally_administered(mac) {
+            return None;
+        }
+
+        self.vendors.get(&[mac.0, mac.1, mac.2]).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vendors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vendors.is_empty()
+    }
+}
+
+/// Whether the U/L bit of mac is set, such addresses are not assigned by the IEEE
+pub fn is_locally_administered(mac: &MacAddr) -> bool {
+    mac.0 & 0b10 != 0
+}
+
+// MA-L,B827EB,Raspberry Pi Foundation,Mitchell Wood House Caldecote GB CB23 7NU
+fn parse_csv_line(line: &str) -> Option<([u8; 3], String)> {
+    let rest = line.strip_prefix("MA-L,")?;
+    let (assignment, rest) = rest.split_once(',')?;
+
+    let vendor = match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"')?.0,
+        None => rest.split(',').next()?,
+    };
+
+    Some((parse_prefix(assignment)?, vendor_name(vendor)?))
+}
+
+// B8-27-EB   (hex)		Raspberry Pi Foundation
+fn parse_txt_line(line: &str) -> Option<([u8; 3], String)> {
+    let (prefix, vendor) = line.split_once("(hex)")?;
+    let prefix: String = prefix.trim().split('-').collect();
+
+    Some((parse_prefix(&prefix)?, vendor_name(vendor)?))
+}
+
+fn parse_prefix(hex: &str) -> Option<[u8; 3]> {
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let mut prefix = [0; 3];
+    for (i, byte) in prefix.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(prefix)
+}
+
+fn vendor_name(vendor: &str) -> Option<String> {
+    let vendor = vendor.trim();
+
+    if vendor.is_empty() {
+        None
+    } else {
+        Some(String::from(vendor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_database_is_parsed() {
+        let database = OuiDatabase::parse(
+            "Registry,Assignment,Organization Name,Organization Address\n\
+             MA-L,00000C,\"Cisco Systems, Inc\",\"170 West Tasman Dr, San Jose\"\n\
+             MA-L,B827EB,Raspberry Pi Foundation,Mitchell Wood House Caldecote GB CB23 7NU\n\
+             MA-M,B827EB1,Not a 24 bit block,\n\
+             MA-L,18B430,,\n",
+        );
+
+        assert_eq!(database.len(), 2);
+        assert_eq!(
+            database.lookup(&MacAddr(0x00, 0x00, 0x0c, 1, 2, 3)),
+            Some("Cisco Systems, Inc")
+        );
+        assert_eq!(
+            database.lookup(&MacAddr(0xb8, 0x27, 0xeb, 1, 2, 3)),
+            Some("Raspberry Pi Foundation")
+        );
+        assert_eq!(database.lookup(&MacAddr(0x18, 0xb4, 0x30, 1, 2, 3)), None);
+    }
+
+    #[test]
+    fn txt_database_is_parsed() {
+        let database = OuiDatabase::parse(
+            "OUI/MA-L                                                    Organization\n\
+             company_id                                                  Organization\n\
+             \n\
+             B8-27-EB   (hex)\t\tRaspberry Pi Foundation\n\
+             B827EB     (base 16)\t\tRaspberry Pi Foundation\n\
+             \t\t\t\tMitchell Wood House\n\
+             00-00-0C   (hex)\t\tCisco Systems, Inc\n",
+        );
+
+        assert_eq!(database.len(), 2);
+        assert_eq!(
+            database.lookup(&MacAddr(0x00, 0x00, 0x0c, 1, 2, 3)),
+            Some("Cisco Systems, Inc")
+        );
+        assert_eq!(
+            database.lookup(&MacAddr(0xb8, 0x27, 0xeb, 1, 2, 3)),
+            Some("Raspberry Pi Foundation")
+        );
+    }
+
+    #[test]
+    fn locally_administered_macs_have_no_vendor() {
+        // the assignment would otherwise match the randomized mac
+        let database = OuiDatabase::parse("MA-L,BA27EB,Locally Assigned Inc,");
+
+        let randomized = MacAddr(0xba, 0x27, 0xeb, 1, 2, 3);
+        assert!(is_locally_administered(&randomized));
+        assert_eq!(database.lookup(&randomized), None);
+        assert!(!is_locally_administered(&MacAddr(
+            0xb8, 0x27, 0xeb, 1, 2, 3
+        )));
+    }
+
+    #[test]
+    fn embedded_database_is_parsed() {
+        let database = OuiDatabase::embedded();
+        assert!(!database.is_empty());
+        assert_eq!(
+            database.lookup(&MacAddr(0x00, 0x00, 0x0c, 1, 2, 3)),
+            Some("Cisco Systems, Inc")
+        );
+    }
+}
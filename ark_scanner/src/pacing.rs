@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+
+/// Token bucket that spreads packets evenly over time
+///
+/// Tokens are added at rate packets per second, up to burst, and every packet takes one.
+/// A pacer without a rate never waits.
+pub struct Pacer {
+    rate: Option<f64>,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Pacer {
+    /// Creates a pacer that starts with a full burst of tokens
+    pub fn new(rate: Option<f64>, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+
+        Self {
+            rate: rate.filter(|rate| *rate > 0.0),
+            burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Pacer that spreads packets evenly over period, sending no faster than max_pps
+    pub fn for_sweep(packets: usize, period: Duration, max_pps: Option<u32>, burst: u32) -> Self {
+        // an empty sweep still keeps the max_pps cap
+        let spread = if period.is_zero() || packets == 0 {
+            None
+        } else {
+            Some(packets as f64 / period.as_secs_f64())
+        };
+
+        let rate = match (spread, max_pps) {
+            (Some(spread), Some(max_pps)) => Some(spread.min(max_pps as f64)),
+            (None, Some(max_pps)) => Some(max_pps as f64),
+            (spread, None) => spread,
+        };
+
+        Self::new(rate, burst)
+    }
+
+    /// Pacer shared by every packet on an interface, sending no faster than max_pps
+    pub fn for_limit(max_pps: Option<u32>, burst: u32) -> Self {
+        Self::new(max_pps.map(f64::from), burst)
+    }
+
+    /// Returns how long until a token is available at now, without taking it
+    pub fn ready_in(&mut self, now: Instant) -> Duration {
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return Duration::ZERO,
+        };
+
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(self.burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / rate)
+        }
+    }
+
+    /// Takes a token, callers check ready_in first
+    pub fn take(&mut self) {
+        if self.rate.is_some() {
+            self.tokens = (self.tokens - 1.0).max(0.0);
+        }
+    }
+
+    /// Takes a token if one is available at now, otherwise returns how long until one is
+    pub fn delay(&mut self, now: Instant) -> Duration {
+        let delay = self.ready_in(now);
+        if delay.is_zero() {
+            self.take();
+        }
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Times at which packets are let through, relative to the creation of pacer
+    fn send_times(pacer: &mut Pacer, packets: usize) -> Vec<Duration> {
+        let start = pacer.last_refill;
+        let mut now = start;
+
+        (0..packets)
+            .map(|_| {
+                loop {
+                    let delay = pacer.delay(now);
+                    if delay.is_zero() {
+                        break;
+                    }
+                    now += delay;
+                }
+                now - start
+            })
+            .collect()
+    }
+
+    fn assert_secs(times: &[Duration], expected: &[f64]) {
+        assert_eq!(times.len(), expected.len());
+        for (time, expected) in times.iter().zip(expected) {
+            assert!((time.as_secs_f64() - expected).abs() < 1e-6, "{times:?}");
+        }
+    }
+
+    #[test]
+    fn sweep_is_spread_over_its_period() {
+        let mut pacer = Pacer::for_sweep(10, Duration::from_secs(20), None, 1);
+
+        // 0.5 pps, the first packet goes out at once
+        let times = send_times(&mut pacer, 10);
+        let expected: Vec<f64> = (0..10).map(|i| f64::from(i) * 2.0).collect();
+        assert_secs(&times, &expected);
+    }
+
+    #[test]
+    fn sweep_is_capped_by_max_pps() {
+        let mut pacer = Pacer::for_sweep(1000, Duration::from_secs(10), Some(10), 1);
+
+        let times = send_times(&mut pacer, 11);
+        assert_secs(&times[10..], &[1.0]);
+
+        // the spread is used when it is slower than max_pps
+        let mut pacer = Pacer::for_sweep(5, Duration::from_secs(10), Some(10), 1);
+        assert_secs(&send_times(&mut pacer, 2), &[0.0, 2.0]);
+    }
+
+    #[test]
+    fn burst_is_sent_at_once_then_paced() {
+        let mut pacer = Pacer::for_sweep(10, Duration::from_secs(10), None, 4);
+        assert_secs(&send_times(&mut pacer, 6), &[0.0, 0.0, 0.0, 0.0, 1.0, 2.0]);
+
+        // a burst of 0 still lets single packets through
+        let mut pacer = Pacer::for_sweep(10, Duration::from_secs(10), None, 0);
+        assert_secs(&send_times(&mut pacer, 2), &[0.0, 1.0]);
+    }
+
+    #[test]
+    fn empty_sweep_keeps_max_pps() {
+        let mut pacer = Pacer::for_sweep(0, Duration::from_secs(10), Some(10), 1);
+        assert_secs(&send_times(&mut pacer, 3), &[0.0, 0.1, 0.2]);
+
+        let mut pacer = Pacer::for_sweep(0, Duration::from_secs(10), None, 1);
+        assert_secs(&send_times(&mut pacer, 3), &[0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn limit_is_max_pps() {
+        let mut limit = Pacer::for_limit(Some(4), 2);
+        assert_secs(&send_times(&mut limit, 4), &[0.0, 0.0, 0.25, 0.5]);
+
+        let mut unlimited = Pacer::for_limit(None, 1);
+        assert_secs(&send_times(&mut unlimited, 3), &[0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn ready_in_does_not_take_a_token() {
+        let mut pacer = Pacer::for_limit(Some(1), 1);
+        let start = pacer.last_refill;
+
+        assert!(pacer.ready_in(start).is_zero());
+        assert!(pacer.ready_in(start).is_zero());
+        pacer.take();
+        assert_eq!(pacer.ready_in(start), Duration::from_secs(1));
+    }
+}
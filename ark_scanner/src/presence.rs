@@ -0,0 +1,788 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use ipnetwork::{Ipv4Network, Ipv6Network};
+use log::log;
+use pnet::packet::{
+    arp::{ArpHardwareTypes, ArpOperations, ArpPacket},
+    ethernet::{EtherTypes, EthernetPacket},
+    Packet,
+};
+use pnet_datalink::MacAddr;
+
+use crate::announce::AnnouncementSources;
+use crate::cache::DeviceDetails;
+use crate::chatter::{ChatterKind, PassiveSources, UdpFrame};
+use crate::dhcp::{DhcpMessageType, DhcpObservation};
+use crate::ndp::NdpObservation;
+use crate::privacy::{shown, shown_ip};
+use crate::targets::TargetSet;
+
+/// Kind of an ARP packet, as far as presence detection is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArpKind {
+    /// Answer to a request, usually one of ours
+    Reply,
+    /// Host asking for another address
+    Request,
+    /// Host announcing its own address (sender and target ip are equal)
+    Gratuitous,
+    /// Address conflict probe, sent with an unspecified sender ip before claiming target ip
+    Probe,
+}
+
+/// ARP packet parsed from an Ethernet frame
+pub struct ArpObservation {
+    pub kind: ArpKind,
+    /// Source mac of the Ethernet frame
+    pub eth_source: MacAddr,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpObservation {
+    /// Parses an Ethernet/IPv4 ARP frame, returns None for any other frame
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let eth_packet = EthernetPacket::new(frame)?;
+
+        if eth_packet.get_ethertype().0 != EtherTypes::Arp.0 {
+            return None;
+        }
+
+        let arp_packet = ArpPacket::new(eth_packet.payload())?;
+
+        if arp_packet.get_hardware_type() != ArpHardwareTypes::Ethernet
+            || arp_packet.get_protocol_type() != EtherTypes::Ipv4
+        {
+            return None;
+        }
+
+        let sender_ip = arp_packet.get_sender_proto_addr();
+        let target_ip = arp_packet.get_target_proto_addr();
+
+        let kind = if sender_ip.is_unspecified() {
+            ArpKind::Probe
+        } else if sender_ip == target_ip {
+            ArpKind::Gratuitous
+        } else if arp_packet.get_operation() == ArpOperations::Reply {
+            ArpKind::Reply
+        } else if arp_packet.get_operation() == ArpOperations::Request {
+            ArpKind::Request
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            kind,
+            eth_source: eth_packet.get_source(),
+            sender_mac: arp_packet.get_sender_hw_addr(),
+            sender_ip,
+            target_ip,
+        })
+    }
+
+    /// Address the sender is present at; a probe's sender only intends to use target ip
+    pub fn claimed_ip(&self) -> Ipv4Addr {
+        match self.kind {
+            ArpKind::Probe => self.target_ip,
+            _ => self.sender_ip,
+        }
+    }
+}
+
+/// Which kinds of ARP packets count as a device being present
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresencePolicy {
+    pub replies: bool,
+    pub gratuitous: bool,
+    pub requests: bool,
+    pub probes: bool,
+}
+
+impl Default for PresencePolicy {
+    /// Replies and gratuitous announcements
+    fn default() -> Self {
+        Self {
+            replies: true,
+            gratuitous: true,
+            requests: false,
+            probes: false,
+        }
+    }
+}
+
+impl PresencePolicy {
+    /// Every kind of ARP packet, as used when listening passively
+    pub fn all() -> Self {
+        Self {
+            replies: true,
+            gratuitous: true,
+            requests: true,
+            probes: true,
+        }
+    }
+
+    pub fn counts(&self, kind: ArpKind) -> bool {
+        match kind {
+            ArpKind::Reply => self.replies,
+            ArpKind::Gratuitous => self.gratuitous,
+            ArpKind::Request => self.requests,
+            ArpKind::Probe => self.probes,
+        }
+    }
+}
+
+impl FromStr for PresencePolicy {
+    type Err = String;
+
+    /// Parses a comma separated list of reply, gratuitous, request and probe
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self {
+            replies: false,
+            gratuitous: false,
+            requests: false,
+            probes: false,
+        };
+
+        for kind in s.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            match kind {
+                "reply" => policy.replies = true,
+                "gratuitous" => policy.gratuitous = true,
+                "request" => policy.requests = true,
+                "probe" => policy.probes = true,
+                _ => return Err(format!("unknown arp kind: {kind}")),
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+impl Display for PresencePolicy {
+    /// Formats the policy as parsed, e.g. reply,gratuitous
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kinds = [
+            (self.replies, "reply"),
+            (self.gratuitous, "gratuitous"),
+            (self.requests, "request"),
+            (self.probes, "probe"),
+        ];
+
+        let enabled: Vec<&str> = kinds
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, k)| *k)
+            .collect();
+        write!(f, "{}", enabled.join(","))
+    }
+}
+
+/// Device whose presence is proven by a received frame
+pub struct Presence {
+    pub mac: MacAddr,
+    /// Address the device is present at, None if it has none yet (e.g. a DHCP discover)
+    pub ip: Option<IpAddr>,
+    /// Details the device announced in the frame
+    pub details: DeviceDetails,
+}
+
+impl Presence {
+    fn at(mac: MacAddr, ip: Option<IpAddr>) -> Self {
+        Self {
+            mac,
+            ip,
+            details: DeviceDetails::default(),
+        }
+    }
+}
+
+/// What a received frame proves about a device
+pub enum DeviceEvent {
+    Present(Presence),
+    /// Device announced it is leaving, e.g. by releasing its DHCP lease
+    Departed(MacAddr),
+}
+
+impl Display for Presence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.ip {
+            Some(ip) => write!(f, "mac: {}, ip: {}", self.mac, ip),
+            None => write!(f, "mac: {}, ip: none", self.mac),
+        }
+    }
+}
+
+/// Decides which received frames prove a device is present
+pub struct PresenceFilter {
+    /// Mac of the scanning machine, its own frames are never counted
+    pub own_mac: Option<MacAddr>,
+    /// Subnet the sender must be in, any address is accepted if None
+    pub subnet: Option<Ipv4Network>,
+    /// Addresses that are counted within the subnet
+    pub targets: TargetSet,
+    /// Whether IPv6 neighbor discovery counts as presence
+    pub ndp: bool,
+    /// IPv6 prefixes of the interface, link-local senders are always accepted
+    pub ipv6_prefixes: Vec<Ipv6Network>,
+    pub policy: PresencePolicy,
+    /// DHCP and mDNS chatter that also counts as presence
+    pub passive_sources: PassiveSources,
+    /// Announcements used to describe devices
+    pub announcement_sources: AnnouncementSources,
+    /// Keeps mac and ip addresses out of logs
+    pub privacy: bool,
+}
+
+impl PresenceFilter {
+    /// Returns the device frame proves present or departed, if any,
+    /// from ARP, NDP or passive sources
+    pub fn observe(&self, frame: &[u8]) -> Option<DeviceEvent> {
+        if let Some(observation) = self.check(frame) {
+            return Some(DeviceEvent::Present(Presence::at(
+                observation.sender_mac,
+                Some(IpAddr::V4(observation.claimed_ip())),
+            )));
+        }
+
+        if let Some(observation) = self.check_ndp(frame) {
+            return Some(DeviceEvent::Present(Presence::at(
+                observation.eth_source,
+                Some(IpAddr::V6(observation.claimed_ip())),
+            )));
+        }
+
+        self.check_chatter(frame)
+    }
+
+    /// Whether frame is of a protocol used to detect or describe devices, counted or not
+    pub fn is_discovery_frame(&self, frame: &[u8]) -> bool {
+        ArpObservation::parse(frame).is_some()
+            || (self.ndp && NdpObservation::parse(frame).is_some())
+            || ((!self.passive_sources.is_empty() || !self.announcement_sources.is_empty())
+                && UdpFrame::parse(frame).map_or(false, |datagram| {
+                    self.passive_sources.classify(&datagram).is_some()
+                        || self.announcement_sources.details(&datagram).is_some()
+                }))
+    }
+
+    /// Returns the device announcing itself in frame over mDNS or SSDP, with what it announced
+    pub fn check_announcement(&self, frame: &[u8]) -> Option<(MacAddr, DeviceDetails)> {
+        if self.announcement_sources.is_empty() {
+            return None;
+        }
+
+        let datagram = UdpFrame::parse(frame)?;
+
+        if Some(datagram.eth_source) == self.own_mac {
+            return None;
+        }
+
+        let details = self.announcement_sources.details(&datagram)?;
+
+        Some((datagram.eth_source, details))
+    }
+
+    /// Returns the ARP packet in frame if it counts as presence of its sender
+    pub fn check(&self, frame: &[u8]) -> Option<ArpObservation> {
+        let observation = ArpObservation::parse(frame)?;
+
+        // skip if machine pings itself
+        if Some(observation.eth_source) == self.own_mac {
+            return None;
+        }
+
+        if !self.policy.counts(observation.kind) {
+            return None;
+        }
+
+        // proxy ARP and spoofed frames carry someone else's mac in the ARP body
+        if observation.eth_source != observation.sender_mac {
+            // routine behind proxy ARP and bridges, where it shows up on every reply
+            log!(
+                log::Level::Debug,
+                "arp sender mac {} does not match ethernet source {}, ignoring",
+                shown(observation.sender_mac, self.privacy),
+                shown(observation.eth_source, self.privacy)
+            );
+            return None;
+        }
+
+        if let Some(subnet) = self.subnet {
+            if !subnet.contains(observation.claimed_ip()) {
+                log!(
+                    log::Level::Trace,
+                    "ignoring arp packet from {} outside of scanned subnet: {}",
+                    shown(observation.sender_mac, self.privacy),
+                    shown(observation.claimed_ip(), self.privacy)
+                );
+                return None;
+            }
+        }
+
+        if !self.targets.contains(observation.claimed_ip()) {
+            log!(
+                log::Level::Trace,
+                "ignoring arp packet from {} outside of scan targets: {}",
+                shown(observation.sender_mac, self.privacy),
+                shown(observation.claimed_ip(), self.privacy)
+            );
+            return None;
+        }
+
+        Some(observation)
+    }
+
+    /// Returns the neighbor discovery message in frame if it counts as presence of its sender
+    pub fn check_ndp(&self, frame: &[u8]) -> Option<NdpObservation> {
+        if !self.ndp {
+            return None;
+        }
+
+        let observation = NdpObservation::parse(frame)?;
+
+        if Some(observation.eth_source) == self.own_mac {
+            return None;
+        }
+
+        if !self.policy.counts(observation.kind) {
+            return None;
+        }
+
+        if let Some(link_layer_addr) = observation.link_layer_addr {
+            if link_layer_addr != observation.eth_source {
+                log!(
+                    log::Level::Debug,
+                    "ndp link-layer address {} does not match ethernet source {}, ignoring",
+                    shown(link_layer_addr, self.privacy),
+                    shown(observation.eth_source, self.privacy)
+                );
+                return None;
+            }
+        }
+
+        let ip = observation.claimed_ip();
+        if !self.is_local_ipv6(ip) {
+            log!(
+                log::Level::Trace,
+                "ignoring ndp message from {} outside of scanned prefixes: {}",
+                shown(observation.eth_source, self.privacy),
+                shown(ip, self.privacy)
+            );
+            return None;
+        }
+
+        Some(observation)
+    }
+
+    /// Returns the device whose DHCP or mDNS chatter is in frame, if it counts as presence
+    /// A DHCP release is a departure of its client.
+    pub fn check_chatter(&self, frame: &[u8]) -> Option<DeviceEvent> {
+        if self.passive_sources.is_empty() {
+            return None;
+        }
+
+        let datagram = UdpFrame::parse(frame)?;
+
+        if Some(datagram.eth_source) == self.own_mac {
+            return None;
+        }
+
+        let kind = self.passive_sources.classify(&datagram)?;
+
+        if kind == ChatterKind::Dhcp {
+            return self.check_dhcp(&datagram);
+        }
+
+        let ip = match datagram.source_ip {
+            // clients without a lease yet send from 0.0.0.0
+            IpAddr::V4(ip) if ip.is_unspecified() => None,
+            IpAddr::V4(ip) if self.is_counted_ipv4(ip) => Some(IpAddr::V4(ip)),
+            IpAddr::V6(ip) if self.ndp && self.is_local_ipv6(ip) => Some(IpAddr::V6(ip)),
+            ip => {
+                log!(
+                    log::Level::Trace,
+                    "ignoring {} chatter from {} outside of scanned addresses: {}",
+                    kind,
+                    shown(datagram.eth_source, self.privacy),
+                    shown_ip(Some(ip), self.privacy)
+                );
+                return None;
+            }
+        };
+
+        Some(DeviceEvent::Present(Presence::at(datagram.eth_source, ip)))
+    }
+
+    fn check_dhcp(&self, datagram: &UdpFrame) -> Option<DeviceEvent> {
+        let dhcp = DhcpObservation::parse(&datagram.payload)?;
+
+        if dhcp.message_type == DhcpMessageType::Release {
+            return self.check_dhcp_release(datagram, &dhcp);
+        }
+
+        // clients without a lease yet have no address to check
+        if let Some(ip) = dhcp.ip() {
+            if !self.is_counted_ipv4(ip) {
+                log!(
+                    log::Level::Trace,
+                    "ignoring dhcp {:?} from {} outside of scanned addresses: {}",
+                    dhcp.message_type,
+                    shown(dhcp.client_mac, self.privacy),
+                    shown(ip, self.privacy)
+                );
+                return None;
+            }
+        }
+
+        Some(DeviceEvent::Present(Presence {
+            mac: dhcp.client_mac,
+            ip: dhcp.ip().map(IpAddr::V4),
+            details: DeviceDetails {
+                hostname: dhcp.hostname,
+                vendor_class: dhcp.vendor_class,
+                dhcp_fingerprint: dhcp.fingerprint,
+                ..DeviceDetails::default()
+            },
+        }))
+    }
+
+    // Anyone can release the lease of any client, only the client itself releasing a counted
+    // address makes it depart
+    fn check_dhcp_release(
+        &self,
+        datagram: &UdpFrame,
+        dhcp: &DhcpObservation,
+    ) -> Option<DeviceEvent> {
+        if datagram.eth_source != dhcp.client_mac {
+            log!(
+                log::Level::Debug,
+                "dhcp release of {} sent by {}, ignoring",
+                shown(dhcp.client_mac, self.privacy),
+                shown(datagram.eth_source, self.privacy)
+            );
+            return None;
+        }
+
+        match dhcp.client_ip {
+            Some(ip) if self.is_counted_ipv4(ip) => Some(DeviceEvent::Departed(dhcp.client_mac)),
+            ip => {
+                log!(
+                    log::Level::Trace,
+                    "ignoring dhcp release from {} outside of scanned addresses: {}",
+                    shown(dhcp.client_mac, self.privacy),
+                    shown_ip(ip.map(IpAddr::V4), self.privacy)
+                );
+                None
+            }
+        }
+    }
+
+    fn is_counted_ipv4(&self, ip: Ipv4Addr) -> bool {
+        self.subnet.map_or(true, |subnet| subnet.contains(ip)) && self.targets.contains(ip)
+    }
+
+    fn is_local_ipv6(&self, ip: Ipv6Addr) -> bool {
+        // fe80::/10
+        let is_link_local = ip.segments()[0] & 0xffc0 == 0xfe80;
+
+        is_link_local || self.ipv6_prefixes.iter().any(|prefix| prefix.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::{
+        arp::{ArpOperation, MutableArpPacket},
+        ethernet::MutableEthernetPacket,
+    };
+
+    use super::*;
+    use crate::chatter::{DHCP_CLIENT_PORT, DHCP_SERVER_PORT};
+    use crate::dhcp::tests::{client_message, release, CLIENT};
+    use crate::privacy::tests::logged;
+    use crate::targets::TargetRange;
+
+    const OTHER: MacAddr = MacAddr(0x00, 0x66, 0x77, 0x88, 0x99, 0xaa);
+
+    fn filter() -> PresenceFilter {
+        PresenceFilter {
+            own_mac: Some(MacAddr(0x02, 0, 0, 0, 0, 1)),
+            subnet: Some("10.0.0.0/24".parse().unwrap()),
+            targets: TargetSet::new(vec![], vec![TargetRange::Host(Ipv4Addr::new(10, 0, 0, 9))]),
+            ndp: false,
+            ipv6_prefixes: vec![],
+            policy: PresencePolicy::default(),
+            passive_sources: PassiveSources {
+                dhcp: true,
+                mdns: false,
+            },
+            announcement_sources: AnnouncementSources::default(),
+            privacy: false,
+        }
+    }
+
+    /// ARP packet from sender_mac in an Ethernet frame from eth_source
+    fn arp(
+        operation: ArpOperation,
+        eth_source: MacAddr,
+        sender_mac: MacAddr,
+        sender_ip: Ipv4Addr,
+        target_ip: Ipv4Addr,
+    ) -> Vec<u8> {
+        let mut arp_buf = [0u8; 28];
+        let mut arp_packet = MutableArpPacket::new(&mut arp_buf).unwrap();
+        arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+        arp_packet.set_protocol_type(EtherTypes::Ipv4);
+        arp_packet.set_hw_addr_len(6);
+        arp_packet.set_proto_addr_len(4);
+        arp_packet.set_operation(operation);
+        arp_packet.set_sender_hw_addr(sender_mac);
+        arp_packet.set_sender_proto_addr(sender_ip);
+        arp_packet.set_target_proto_addr(target_ip);
+
+        let mut eth_buf = vec![0u8; 14 + arp_buf.len()];
+        let mut eth_packet = MutableEthernetPacket::new(&mut eth_buf).unwrap();
+        eth_packet.set_destination(MacAddr::broadcast());
+        eth_packet.set_source(eth_source);
+        eth_packet.set_ethertype(EtherTypes::Arp);
+        eth_packet.set_payload(&arp_buf);
+
+        eth_buf
+    }
+
+    /// ARP reply from CLIENT at sender_ip
+    fn reply(sender_ip: Ipv4Addr) -> Vec<u8> {
+        arp(
+            ArpOperations::Reply,
+            CLIENT,
+            CLIENT,
+            sender_ip,
+            Ipv4Addr::new(10, 0, 0, 1),
+        )
+    }
+
+    fn datagram(eth_source: MacAddr, payload: Vec<u8>) -> UdpFrame {
+        UdpFrame {
+            eth_source,
+            source_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)),
+            source_port: DHCP_CLIENT_PORT,
+            destination_port: DHCP_SERVER_PORT,
+            payload,
+        }
+    }
+
+    #[test]
+    fn client_releasing_counted_address_departs() {
+        let release = datagram(CLIENT, release(Ipv4Addr::new(10, 0, 0, 7)));
+
+        match filter().check_dhcp(&release) {
+            Some(DeviceEvent::Departed(mac)) => assert_eq!(mac, CLIENT),
+            _ => panic!("release did not depart its client"),
+        }
+    }
+
+    #[test]
+    fn release_of_another_client_is_ignored() {
+        let spoofed = datagram(OTHER, release(Ipv4Addr::new(10, 0, 0, 7)));
+
+        assert!(filter().check_dhcp(&spoofed).is_none());
+    }
+
+    #[test]
+    fn release_of_uncounted_address_is_ignored() {
+        for ciaddr in [
+            // outside the subnet
+            Ipv4Addr::new(192, 168, 1, 7),
+            // excluded from the targets
+            Ipv4Addr::new(10, 0, 0, 9),
+            // no address
+            Ipv4Addr::UNSPECIFIED,
+        ] {
+            let release = datagram(CLIENT, release(ciaddr));
+            assert!(filter().check_dhcp(&release).is_none(), "{ciaddr}");
+        }
+    }
+
+    /// ARP packet from CLIENT in an Ethernet frame it sent
+    fn from_client(operation: ArpOperation, sender_ip: [u8; 4], target_ip: [u8; 4]) -> Vec<u8> {
+        arp(
+            operation,
+            CLIENT,
+            CLIENT,
+            Ipv4Addr::from(sender_ip),
+            Ipv4Addr::from(target_ip),
+        )
+    }
+
+    #[test]
+    fn arp_packets_are_classified() {
+        for (frame, kind, claimed_ip) in [
+            (
+                from_client(ArpOperations::Reply, [10, 0, 0, 7], [10, 0, 0, 1]),
+                ArpKind::Reply,
+                [10, 0, 0, 7],
+            ),
+            (
+                from_client(ArpOperations::Request, [10, 0, 0, 7], [10, 0, 0, 1]),
+                ArpKind::Request,
+                [10, 0, 0, 7],
+            ),
+            // announcements are gratuitous whether sent as request or reply
+            (
+                from_client(ArpOperations::Request, [10, 0, 0, 7], [10, 0, 0, 7]),
+                ArpKind::Gratuitous,
+                [10, 0, 0, 7],
+            ),
+            (
+                from_client(ArpOperations::Reply, [10, 0, 0, 7], [10, 0, 0, 7]),
+                ArpKind::Gratuitous,
+                [10, 0, 0, 7],
+            ),
+            // a probe claims its target
+            (
+                from_client(ArpOperations::Request, [0, 0, 0, 0], [10, 0, 0, 7]),
+                ArpKind::Probe,
+                [10, 0, 0, 7],
+            ),
+        ] {
+            let observation = ArpObservation::parse(&frame).unwrap();
+            assert_eq!(observation.kind, kind);
+            assert_eq!(observation.claimed_ip(), Ipv4Addr::from(claimed_ip));
+            assert_eq!(observation.sender_mac, CLIENT);
+        }
+
+        let unknown = from_client(ArpOperation::new(9), [10, 0, 0, 7], [10, 0, 0, 1]);
+        assert!(ArpObservation::parse(&unknown).is_none());
+        assert!(ArpObservation::parse(&unknown[..30]).is_none());
+    }
+
+    #[test]
+    fn policy_decides_which_kinds_count() {
+        let reply = from_client(ArpOperations::Reply, [10, 0, 0, 7], [10, 0, 0, 1]);
+        let request = from_client(ArpOperations::Request, [10, 0, 0, 7], [10, 0, 0, 1]);
+        let gratuitous = from_client(ArpOperations::Request, [10, 0, 0, 7], [10, 0, 0, 7]);
+        let probe = from_client(ArpOperations::Request, [0, 0, 0, 0], [10, 0, 0, 7]);
+
+        for (policy, counted) in [
+            ("reply", [true, false, false, false]),
+            ("request", [false, true, false, false]),
+            ("gratuitous", [false, false, true, false]),
+            ("probe", [false, false, false, true]),
+            ("reply,gratuitous", [true, false, true, false]),
+            ("", [false, false, false, false]),
+        ] {
+            let filter = PresenceFilter {
+                policy: policy.parse().unwrap(),
+                ..filter()
+            };
+
+            for (frame, counted) in [&reply, &request, &gratuitous, &probe].iter().zip(counted) {
+                assert_eq!(filter.check(frame).is_some(), counted, "{policy}");
+            }
+        }
+
+        assert_eq!(PresencePolicy::default().to_string(), "reply,gratuitous");
+        assert_eq!(
+            PresencePolicy::all(),
+            "probe, request,reply,gratuitous".parse().unwrap()
+        );
+        assert!("reply,rarp".parse::<PresencePolicy>().is_err());
+    }
+
+    #[test]
+    fn arp_sender_must_match_ethernet_source() {
+        // a bridge or proxy relaying the reply of CLIENT
+        let relayed = arp(
+            ArpOperations::Reply,
+            OTHER,
+            CLIENT,
+            Ipv4Addr::new(10, 0, 0, 7),
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        assert!(filter().check(&relayed).is_none());
+
+        let own = arp(
+            ArpOperations::Reply,
+            MacAddr(0x02, 0, 0, 0, 0, 1),
+            MacAddr(0x02, 0, 0, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        assert!(filter().check(&own).is_none());
+    }
+
+    #[test]
+    fn senders_must_be_counted_addresses() {
+        let counted = filter().check(&reply(Ipv4Addr::new(10, 0, 0, 7))).unwrap();
+        assert_eq!(counted.sender_ip, Ipv4Addr::new(10, 0, 0, 7));
+
+        // outside the subnet, e.g. a router answering for a remote host through proxy ARP
+        let proxied = arp(
+            ArpOperations::Reply,
+            OTHER,
+            OTHER,
+            Ipv4Addr::new(192, 168, 1, 7),
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        assert!(filter().check(&proxied).is_none());
+
+        // excluded from the targets
+        assert!(filter().check(&reply(Ipv4Addr::new(10, 0, 0, 9))).is_none());
+
+        // any address counts without a subnet
+        let unbounded = PresenceFilter {
+            subnet: None,
+            ..filter()
+        };
+        assert!(unbounded.check(&proxied).is_some());
+    }
+
+    #[test]
+    fn ignored_frames_are_logged_without_addresses_in_privacy_mode() {
+        let filter = PresenceFilter {
+            privacy: true,
+            ..filter()
+        };
+
+        let logs = logged(|| {
+            // outside the subnet, excluded from the targets, and sent by another mac
+            assert!(filter
+                .check(&reply(Ipv4Addr::new(192, 168, 1, 7)))
+                .is_none());
+            assert!(filter.check(&reply(Ipv4Addr::new(10, 0, 0, 9))).is_none());
+            let proxied = arp(
+                ArpOperations::Reply,
+                OTHER,
+                CLIENT,
+                Ipv4Addr::new(10, 0, 0, 7),
+                Ipv4Addr::new(10, 0, 0, 1),
+            );
+            assert!(filter.check(&proxied).is_none());
+
+            // dhcp request for an uncounted address, and releases not counted
+            let request = client_message(Ipv4Addr::new(192, 168, 1, 7), &[53, 1, 3]);
+            assert!(filter.check_dhcp(&datagram(CLIENT, request)).is_none());
+            let outside = release(Ipv4Addr::new(192, 168, 1, 7));
+            assert!(filter.check_dhcp(&datagram(CLIENT, outside)).is_none());
+            let spoofed = release(Ipv4Addr::new(10, 0, 0, 7));
+            assert!(filter.check_dhcp(&datagram(OTHER, spoofed)).is_none());
+        });
+
+        assert_eq!(logs.len(), 6, "{logs:?}");
+        for log in &logs {
+            for raw in [
+                CLIENT.to_string(),
+                OTHER.to_string(),
+                String::from("192.168.1.7"),
+                String::from("10.0.0.9"),
+                String::from("10.0.0.7"),
+            ] {
+                assert!(!log.contains(&raw), "{log}");
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fmt::Display,
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use pnet_datalink::MacAddr;
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Shown in logs instead of raw mac addresses and addresses derived from them
+pub const REDACTED: &str = "<redacted>";
+
+/// Replaces mac addresses by keyed hashes, salted per UTC day
+///
+/// The pseudonym of a mac is HMAC-SHA256(daily salt, mac), truncated to a mac address that keeps
+/// the U/L bit so randomized macs are still recognized. The daily salt is derived from the key,
+/// so pseudonyms cannot be linked across days and the same device shows up under a new
+/// pseudonym after midnight UTC, which its cached entry is moved to when it is next seen.
+/// Without a configured key a random one is generated, and pseudonyms also change on every
+/// restart.
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: Option<&str>) -> Self {
+        let key = match key {
+            Some(key) => key.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                key
+            }
+        };
+
+        Self { key }
+    }
+
+    /// Pseudonym of mac for the day of at
+    pub fn pseudonym(&self, mac: &MacAddr, at: SystemTime) -> MacAddr {
+        self.pseudonym_on(mac, day(at))
+    }
+
+    /// Pseudonym of mac for the day before at, which devices seen before midnight UTC are still
+    /// cached under
+    pub fn previous_pseudonym(&self, mac: &MacAddr, at: SystemTime) -> MacAddr {
+        self.pseudonym_on(mac, day(at).saturating_sub(1))
+    }
+
+    fn pseudonym_on(&self, mac: &MacAddr, day: u64) -> MacAddr {
+        let salt = keyed_hash(&self.key, &day.to_be_bytes());
+        let hash = keyed_hash(&salt, &[mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]);
+
+        // unicast, with the U/L bit of mac
+        let first = (hash[0] & !0b11) | (mac.0 & 0b10);
+
+        MacAddr::new(first, hash[1], hash[2], hash[3], hash[4], hash[5])
+    }
+}
+
+/// Returns value, or a redaction marker if privacy is enabled
+pub fn shown(value: impl Display, privacy: bool) -> String {
+    if privacy {
+        String::from(REDACTED)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Returns ip for logs, redacted if privacy is enabled
+///
+/// IPv6 addresses can embed the mac, and an IPv4 address identifies a device as well as its mac.
+pub fn shown_ip(ip: Option<IpAddr>, privacy: bool) -> String {
+    match ip {
+        Some(ip) => shown(ip, privacy),
+        None => String::from("none"),
+    }
+}
+
+/// Days since the UNIX epoch, in UTC
+fn day(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / SECS_PER_DAY)
+}
+
+fn keyed_hash(key: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{cell::RefCell, sync::Once};
+
+    use log::{LevelFilter, Log, Metadata, Record};
+
+    use super::*;
+
+    thread_local! {
+        static LOGGED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Keeps messages per thread, so tests running in parallel only see their own
+    struct ThreadLogger;
+
+    impl Log for ThreadLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            LOGGED.with(|logged| logged.borrow_mut().push(record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: ThreadLogger = ThreadLogger;
+    static INIT: Once = Once::new();
+
+    /// Messages logged at any level by the current thread while running f
+    pub(crate) fn logged(f: impl FnOnce()) -> Vec<String> {
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).expect("no other logger is set in unit tests");
+            log::set_max_level(LevelFilter::Trace);
+        });
+
+        LOGGED.with(|logged| logged.borrow_mut().clear());
+        f();
+        LOGGED.with(|logged| logged.take())
+    }
+
+    #[test]
+    fn addresses_are_redacted_in_privacy_mode() {
+        for ip in ["10.0.0.7", "fe80::211:22ff:fe33:4455"] {
+            let ip = Some(ip.parse().unwrap());
+            assert_eq!(shown_ip(ip, true), REDACTED);
+            assert_ne!(shown_ip(ip, false), REDACTED);
+        }
+
+        assert_eq!(shown_ip(None, true), "none");
+    }
+}
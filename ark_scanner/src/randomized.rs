@@ -0,0 +1,222 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::Ipv4Addr,
+    time::Duration,
+};
+
+use pnet_datalink::MacAddr;
+
+use crate::cache::{DeviceEntry, MacCache};
+use crate::oui::is_locally_administered;
+
+/// Whether mac is likely randomized, i.e. locally administered rather than assigned by the IEEE
+pub fn is_randomized(mac: &MacAddr) -> bool {
+    is_locally_administered(mac)
+}
+
+// What two entries of the same device have in common
+#[derive(PartialEq, Eq, Hash)]
+enum DeviceKey {
+    Ip(Ipv4Addr),
+    /// Lowercase, host names are compared case insensitively
+    Hostname(String),
+    DhcpFingerprint(String),
+}
+
+fn device_keys(entry: &DeviceEntry) -> Vec<DeviceKey> {
+    let details = &entry.details;
+
+    entry
+        .ip()
+        .map(DeviceKey::Ip)
+        .into_iter()
+        .chain(
+            details
+                .hostname
+                .as_ref()
+                .map(|hostname| DeviceKey::Hostname(hostname.to_ascii_lowercase())),
+        )
+        .chain(
+            details
+                .dhcp_fingerprint
+                .clone()
+                .map(DeviceKey::DhcpFingerprint),
+        )
+        .collect()
+}
+
+/// Returns the cached devices that were likely replaced by a randomized mac of the same device
+///
+/// Phones rotate randomized macs and reconnect under a new one, while the old one stays cached
+/// until it expires. A device is replaced if a randomized mac first showed up at most window
+/// after it was last seen, with the same ip, host name or DHCP fingerprint. Devices seen at the
+/// same time are never merged, so two phones announcing the same host name are both counted.
+pub fn replaced_macs(cache: &MacCache, window: Duration) -> HashSet<MacAddr> {
+    // only devices sharing a key are compared
+    let mut index: HashMap<DeviceKey, Vec<(&MacAddr, &DeviceEntry)>> = HashMap::new();
+    for (mac, entry) in cache.iter() {
+        for key in device_keys(entry) {
+            index.entry(key).or_default().push((mac, entry));
+        }
+    }
+
+    let mut replaced = HashSet::new();
+
+    for (mac, entry) in cache.iter().filter(|(mac, _)| is_randomized(mac)) {
+        let candidates = device_keys(entry)
+            .into_iter()
+            .filter_map(|key| index.get(&key))
+            .flatten();
+
+        for (old_mac, old_entry) in candidates {
+            if *old_mac == mac || replaced.contains(*old_mac) {
+                continue;
+            }
+
+            let gap = match entry.first_seen.duration_since(old_entry.last_seen) {
+                Ok(gap) => gap,
+                // both were seen at once
+                Err(_) => continue,
+            };
+
+            if gap <= window {
+                replaced.insert(**old_mac);
+            }
+        }
+    }
+
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::IpAddr,
+        time::{Instant, UNIX_EPOCH},
+    };
+
+    use super::*;
+    use crate::cache::{DeviceDetails, Sighting};
+
+    const WINDOW: Duration = Duration::from_secs(300);
+    const OLD: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+    const RANDOMIZED: MacAddr = MacAddr(0x02, 0x11, 0x22, 0x33, 0x44, 0x66);
+
+    /// Sighting at secs after an arbitrary start
+    fn sighting(secs: u64, ip: Option<[u8; 4]>, details: DeviceDetails) -> Sighting {
+        Sighting {
+            ip: ip.map(|ip| IpAddr::V4(Ipv4Addr::from(ip))),
+            interface: String::from("eth0"),
+            at: Instant::now(),
+            wall_time: UNIX_EPOCH + Duration::from_secs(1_000_000 + secs),
+            details,
+        }
+    }
+
+    fn hostname(hostname: &str) -> DeviceDetails {
+        DeviceDetails {
+            hostname: Some(String::from(hostname)),
+            ..DeviceDetails::default()
+        }
+    }
+
+    fn fingerprint(fingerprint: &str) -> DeviceDetails {
+        DeviceDetails {
+            dhcp_fingerprint: Some(String::from(fingerprint)),
+            ..DeviceDetails::default()
+        }
+    }
+
+    /// Cache where OLD was seen from 0 to old_last_seen and RANDOMIZED from randomized_first_seen
+    fn cache(
+        old_last_seen: u64,
+        old: (Option<[u8; 4]>, DeviceDetails),
+        randomized_first_seen: u64,
+        randomized: (Option<[u8; 4]>, DeviceDetails),
+    ) -> MacCache {
+        let mut cache = MacCache::new();
+        cache.record(OLD, sighting(0, old.0, old.1.clone()));
+        cache.record(OLD, sighting(old_last_seen, old.0, old.1));
+        cache.record(
+            RANDOMIZED,
+            sighting(randomized_first_seen, randomized.0, randomized.1),
+        );
+        cache
+    }
+
+    #[test]
+    fn device_reconnecting_within_window_is_replaced() {
+        let ip = Some([10, 0, 0, 7]);
+
+        let within = cache(100, (ip, hostname("a")), 100 + 300, (ip, hostname("b")));
+        assert_eq!(replaced_macs(&within, WINDOW), HashSet::from([OLD]));
+
+        let outside = cache(100, (ip, hostname("a")), 100 + 301, (ip, hostname("b")));
+        assert!(replaced_macs(&outside, WINDOW).is_empty());
+    }
+
+    #[test]
+    fn devices_seen_at_the_same_time_are_not_merged() {
+        let ip = Some([10, 0, 0, 7]);
+
+        // the randomized mac showed up before the old one was last seen
+        let overlapping = cache(100, (ip, hostname("phone")), 50, (ip, hostname("phone")));
+        assert!(replaced_macs(&overlapping, WINDOW).is_empty());
+    }
+
+    #[test]
+    fn devices_match_on_ip_hostname_or_fingerprint() {
+        let matching = [
+            (
+                (Some([10, 0, 0, 7]), DeviceDetails::default()),
+                (Some([10, 0, 0, 7]), DeviceDetails::default()),
+            ),
+            (
+                (Some([10, 0, 0, 7]), hostname("Johns-iPhone")),
+                (Some([10, 0, 0, 8]), hostname("johns-iphone")),
+            ),
+            (
+                (None, fingerprint("1,121,3,6")),
+                (None, fingerprint("1,121,3,6")),
+            ),
+        ];
+
+        for (old, randomized) in matching {
+            let cache = cache(100, old, 160, randomized);
+            assert_eq!(replaced_macs(&cache, WINDOW), HashSet::from([OLD]));
+        }
+
+        let different = [
+            (
+                (Some([10, 0, 0, 7]), hostname("phone")),
+                (Some([10, 0, 0, 8]), hostname("tablet")),
+            ),
+            (
+                (None, fingerprint("1,121,3,6")),
+                (None, fingerprint("1,3,6")),
+            ),
+            // nothing known about either
+            (
+                (None, DeviceDetails::default()),
+                (None, DeviceDetails::default()),
+            ),
+        ];
+
+        for (old, randomized) in different {
+            let cache = cache(100, old, 160, randomized);
+            assert!(replaced_macs(&cache, WINDOW).is_empty());
+        }
+    }
+
+    #[test]
+    fn only_randomized_macs_replace_devices() {
+        let ip = Some([10, 0, 0, 7]);
+        let mut cache = MacCache::new();
+        cache.record(RANDOMIZED, sighting(0, ip, DeviceDetails::default()));
+        cache.record(OLD, sighting(60, ip, DeviceDetails::default()));
+
+        assert!(replaced_macs(&cache, WINDOW).is_empty());
+        assert!(is_randomized(&RANDOMIZED));
+        assert!(!is_randomized(&OLD));
+    }
+}
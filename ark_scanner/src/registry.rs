@@ -0,0 +1,191 @@
+use std::time::SystemTime;
+
+use log::log;
+use pnet_datalink::MacAddr;
+
+use crate::cache::{DeviceDetails, MacCache, Sighting};
+use crate::classify::DeviceClassifier;
+use crate::presence::Presence;
+use crate::privacy::{shown, shown_ip, Pseudonymizer};
+
+/// Records observed devices in a [`MacCache`], resolving their vendor and class
+///
+/// In privacy mode devices are cached under their pseudonym, and only pseudonyms are logged.
+pub struct DeviceRegistry {
+    classifier: DeviceClassifier,
+    pseudonymizer: Option<Pseudonymizer>,
+}
+
+impl DeviceRegistry {
+    pub fn new(classifier: DeviceClassifier, pseudonymizer: Option<Pseudonymizer>) -> Self {
+        Self {
+            classifier,
+            pseudonymizer,
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.pseudonymizer.is_some()
+    }
+
+    /// Key mac is cached under when seen at
+    pub fn key(&self, mac: &MacAddr, at: SystemTime) -> MacAddr {
+        match &self.pseudonymizer {
+            Some(pseudonymizer) => pseudonymizer.pseudonym(mac, at),
+            None => *mac,
+        }
+    }
+
+    /// Key mac is cached under when seen at, moving its entry to the key if it was cached under
+    /// the pseudonym of the previous day
+    ///
+    /// Pseudonyms change at midnight UTC, without this a device present across midnight would be
+    /// counted twice until its old entry expires, and could not depart.
+    fn current_key(&self, cache: &mut MacCache, mac: &MacAddr, at: SystemTime) -> MacAddr {
+        let key = self.key(mac, at);
+
+        if let Some(pseudonymizer) = &self.pseudonymizer {
+            let previous = pseudonymizer.previous_pseudonym(mac, at);
+            if previous != key && cache.get(&previous).is_some() {
+                log!(log::Level::Trace, "device {} is now {}", previous, key);
+                cache.rekey(&previous, key);
+            }
+        }
+
+        key
+    }
+
+    /// Records a sighting of the present device
+    pub fn record(&self, cache: &mut MacCache, presence: Presence, mut sighting: Sighting) {
+        let key = self.current_key(cache, &presence.mac, sighting.wall_time);
+
+        log!(
+            log::Level::Trace,
+            "incoming packet mac: {}, ip: {}",
+            key,
+            shown_ip(presence.ip, self.is_private())
+        );
+
+        sighting.details = presence.details;
+        cache.record(key, sighting);
+
+        if let Some(entry) = cache.get_mut(&key) {
+            self.classifier.describe(&presence.mac, entry);
+        }
+    }
+
+    /// Attaches details mac announced at to its entry, if it is cached
+    pub fn enrich(
+        &self,
+        cache: &mut MacCache,
+        mac: &MacAddr,
+        details: DeviceDetails,
+        at: SystemTime,
+    ) {
+        let key = self.current_key(cache, mac, at);
+        let shown_details = shown(format!("{details:?}"), self.is_private());
+
+        if cache.enrich(&key, details) {
+            log!(
+                log::Level::Trace,
+                "device {} announced {}",
+                key,
+                shown_details
+            );
+
+            if let Some(entry) = cache.get_mut(&key) {
+                self.classifier.describe(mac, entry);
+            }
+        }
+    }
+
+    /// Removes the device that announced it left at
+    pub fn depart(&self, cache: &mut MacCache, mac: &MacAddr, at: SystemTime) {
+        let key = self.current_key(cache, mac, at);
+
+        log!(log::Level::Trace, "device {} departed, deleting", key);
+        cache.delete(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant, UNIX_EPOCH},
+    };
+
+    use super::*;
+    use crate::oui::OuiDatabase;
+
+    const MAC: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+
+    fn registry() -> DeviceRegistry {
+        DeviceRegistry::new(
+            DeviceClassifier::new(OuiDatabase::embedded(), vec![]),
+            Some(Pseudonymizer::new(Some("key"))),
+        )
+    }
+
+    fn see(registry: &DeviceRegistry, cache: &mut MacCache, at: SystemTime) {
+        let presence = Presence {
+            mac: MAC,
+            ip: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+            details: DeviceDetails::default(),
+        };
+        let sighting = Sighting {
+            ip: presence.ip,
+            interface: String::from("eth0"),
+            at: Instant::now(),
+            wall_time: at,
+            details: DeviceDetails::default(),
+        };
+
+        registry.record(cache, presence, sighting);
+    }
+
+    // 2026-10-17 23:59:00 UTC
+    fn before_midnight() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_792_281_540)
+    }
+
+    // 2026-10-18 00:01:00 UTC
+    fn after_midnight() -> SystemTime {
+        before_midnight() + Duration::from_secs(120)
+    }
+
+    #[test]
+    fn device_keeps_one_entry_across_midnight() {
+        let registry = registry();
+        let mut cache = MacCache::new();
+
+        see(&registry, &mut cache, before_midnight());
+        let first_seen = cache
+            .get(&registry.key(&MAC, before_midnight()))
+            .unwrap()
+            .first_seen;
+
+        see(&registry, &mut cache, after_midnight());
+
+        assert_ne!(
+            registry.key(&MAC, before_midnight()),
+            registry.key(&MAC, after_midnight())
+        );
+        assert_eq!(cache.size(), 1);
+
+        let entry = cache.get(&registry.key(&MAC, after_midnight())).unwrap();
+        assert_eq!(entry.first_seen, first_seen);
+        assert_eq!(entry.reply_count, 2);
+    }
+
+    #[test]
+    fn device_seen_before_midnight_departs_after_it() {
+        let registry = registry();
+        let mut cache = MacCache::new();
+
+        see(&registry, &mut cache, before_midnight());
+        registry.depart(&mut cache, &MAC, after_midnight());
+
+        assert_eq!(cache.size(), 0);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use log::log;
+
+use crate::check::ensure_valid;
+use crate::config::ScannerOptions;
+use crate::error::ConfigErr;
+use crate::sync::lock;
+
+/// Options of a running scanner, shared by its threads and replaced on reload
+///
+/// Threads pick up reloaded options at their next period. Options that only take effect when
+/// the scanner starts (interfaces, mode, capture, ...) are kept until it is restarted.
+pub struct LiveOptions {
+    current: Mutex<Arc<ScannerOptions>>,
+    generation: AtomicU64,
+}
+
+impl LiveOptions {
+    pub fn new(options: ScannerOptions) -> Self {
+        Self {
+            current: Mutex::new(Arc::new(options)),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Options currently in effect
+    pub fn get(&self) -> Arc<ScannerOptions> {
+        Arc::clone(&lock(&self.current))
+    }
+
+    /// Number of reloads so far, cheap enough to poll for every received frame
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Applies the options that can change while the scanner runs
+    ///
+    /// Returns the keys of changed options that need a restart, they keep their current value.
+    /// Options with errors are rejected, like they would be when the scanner starts.
+    pub fn reload(&self, options: ScannerOptions) -> Result<Vec<&'static str>, ConfigErr> {
+        ensure_valid(&options)?;
+
+        let mut current = lock(&self.current);
+        let deferred = restart_only_changes(&current, &options);
+
+        let mut applied = options;
+        applied.mode = current.mode;
+        applied.interfaces = current.interfaces.clone();
+        applied.ndp = current.ndp;
+        applied.capture = current.capture.clone();
+        applied.privacy = current.privacy;
+        applied.privacy_key = current.privacy_key.clone();
+        applied.oui_db_path = current.oui_db_path.clone();
+        applied.class_rules = current.class_rules.clone();
+        applied.trace = current.trace;
+        applied.reconnect_cmd = current.reconnect_cmd.clone();
+
+        *current = Arc::new(applied);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+
+        Ok(deferred)
+    }
+}
+
+// Options that open channels, files or caches when the scanner starts
+fn restart_only_changes(current: &ScannerOptions, new: &ScannerOptions) -> Vec<&'static str> {
+    [
+        ("mode", current.mode != new.mode),
+        ("interfaces", current.interfaces != new.interfaces),
+        ("detection.ndp", current.ndp != new.ndp),
+        ("sinks.capture_path", current.capture != new.capture),
+        ("privacy.enabled", current.privacy != new.privacy),
+        ("privacy.key", current.privacy_key != new.privacy_key),
+        (
+            "devices.oui_db_path",
+            current.oui_db_path != new.oui_db_path,
+        ),
+        (
+            "devices.class_rules",
+            current.class_rules != new.class_rules,
+        ),
+        ("trace", current.trace != new.trace),
+        ("reconnect_cmd", current.reconnect_cmd != new.reconnect_cmd),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(key, _)| key)
+    .collect()
+}
+
+/// Loads the options a running scanner is reloaded with
+///
+/// Options are reloaded on SIGHUP, and when one of the watched files is modified.
+pub struct Reloader {
+    load: Box<dyn FnMut() -> Result<ScannerOptions, ConfigErr>>,
+    watched: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Reloader {
+    pub fn new(load: impl FnMut() -> Result<ScannerOptions, ConfigErr> + 'static) -> Self {
+        Self {
+            load: Box::new(load),
+            watched: vec![],
+        }
+    }
+
+    /// Also reloads when the file at path is modified
+    pub fn watch(mut self, path: PathBuf) -> Self {
+        let modified = modified(&path);
+        self.watched.push((path, modified));
+        self
+    }
+
+    /// Loads the options again
+    pub fn load(&mut self) -> Result<ScannerOptions, ConfigErr> {
+        (self.load)()
+    }
+
+    /// Whether a watched file was modified since the last call
+    pub fn is_modified(&mut self) -> bool {
+        let mut is_modified = false;
+
+        for (path, last_modified) in &mut self.watched {
+            let modified = modified(path);
+            if modified != *last_modified {
+                log!(log::Level::Info, "{} was modified", path.display());
+                *last_modified = modified;
+                is_modified = true;
+            }
+        }
+
+        is_modified
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env};
+
+    use super::*;
+    use crate::config::{load_scanner_opts, ConfigLayers};
+
+    /// Options with required ones set, then each of set
+    fn options(set: &[(&str, &str)]) -> ScannerOptions {
+        let required = [
+            ("trace", "false"),
+            ("reconnect_cmd", "true"),
+            ("timing.mac_addr_timeout_secs", "300"),
+            ("timing.arp_scan_period_secs", "1"),
+            ("timing.mac_cache_log_period_secs", "5"),
+        ];
+
+        let mut layers = ConfigLayers::with_env(HashMap::new());
+        for (key, value) in required.iter().chain(set) {
+            layers = layers.with_override(key, value, "--set").unwrap();
+        }
+
+        load_scanner_opts(&layers).unwrap()
+    }
+
+    #[test]
+    fn live_options_are_applied() {
+        let live = LiveOptions::new(options(&[]));
+
+        let deferred = live
+            .reload(options(&[
+                ("location", "lobby"),
+                ("timing.arp_scan_period_secs", "2"),
+                ("timing.max_pps", "10"),
+                ("targets.include", "10.0.0.2-10.0.0.5"),
+                ("sinks.api_url", "https://example.com"),
+                ("sinks.api_retry_limit", "3"),
+                ("detection.passive_sources", "dhcp"),
+            ]))
+            .unwrap();
+        assert!(deferred.is_empty(), "{deferred:?}");
+        assert_eq!(live.generation(), 1);
+
+        let applied = live.get();
+        assert_eq!(applied.location, "lobby");
+        assert_eq!(applied.arp_scan_period, 2);
+        assert_eq!(applied.max_pps, Some(10));
+        assert_eq!(applied.targets.include.len(), 1);
+        assert_eq!(applied.log_api_url.as_deref(), Some("https://example.com"));
+        assert!(applied.passive_sources.dhcp);
+    }
+
+    #[test]
+    fn restart_only_options_are_deferred() {
+        let oui = env::temp_dir().join(format!("ark-scanner-{}-reload.csv", std::process::id()));
+        fs::write(&oui, "MA-L,B827EB,Raspberry Pi Foundation,\n").unwrap();
+        let oui = oui.to_string_lossy().into_owned();
+
+        for (key, value) in [
+            ("mode", "passive"),
+            ("interfaces", "eth1"),
+            ("detection.ndp", "true"),
+            ("sinks.capture_path", "/tmp/ark-scanner.pcapng"),
+            ("privacy.enabled", "true"),
+            ("privacy.key", "secret"),
+            ("devices.oui_db_path", oui.as_str()),
+            ("devices.class_rules", "hostname:kiosk=iot"),
+            ("trace", "true"),
+            ("reconnect_cmd", "false"),
+        ] {
+            let live = LiveOptions::new(options(&[]));
+
+            let deferred = live.reload(options(&[(key, value)])).unwrap();
+            assert_eq!(deferred, vec![key]);
+
+            // the other options are applied, the restart only ones keep their value
+            assert_eq!(live.generation(), 1);
+            assert!(
+                restart_only_changes(&live.get(), &options(&[])).is_empty(),
+                "{key}"
+            );
+        }
+
+        fs::remove_file(oui).unwrap();
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let live = LiveOptions::new(options(&[]));
+
+        for set in [
+            ("timing.arp_scan_period_secs", "0"),
+            ("timing.reprobe_interval_secs", "0"),
+            ("sinks.api_url", "https://example.com"),
+        ] {
+            assert!(matches!(
+                live.reload(options(&[set, ("location", "lobby")])),
+                Err(ConfigErr::Rejected { .. })
+            ));
+        }
+
+        assert_eq!(live.generation(), 0);
+        assert_eq!(live.get().location, options(&[]).location);
+    }
+}
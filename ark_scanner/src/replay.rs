@@ -0,0 +1,196 @@
+use std::{
+    io::Read,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use ipnetwork::Ipv4Network;
+use log::log;
+use pnet_datalink::MacAddr;
+
+use crate::cache::{DeviceDetails, MacCache, Sighting};
+use crate::capture::CaptureReader;
+use crate::classify::DeviceClassifier;
+use crate::config::ScannerOptions;
+use crate::error::{ArpScannerErr, ConfigErr, SinkErr};
+use crate::presence::{DeviceEvent, PresenceFilter};
+use crate::privacy::Pseudonymizer;
+use crate::registry::DeviceRegistry;
+use crate::scanner::JANITOR_PERIOD;
+
+/// Interface name recorded for devices seen in a capture
+pub const REPLAY_INTERFACE: &str = "replay";
+
+/// How a capture is pushed through the scanner
+pub struct ReplayOptions {
+    /// Waits between frames to match the original capture timing,
+    /// otherwise replays as fast as possible
+    pub realtime: bool,
+    /// Mac address of the machine the capture was taken on, its frames are not counted
+    pub own_mac: Option<MacAddr>,
+    /// Subnet the capture was taken in, devices outside of it are not counted
+    pub subnet: Option<Ipv4Network>,
+}
+
+/// Device count the live scanner would have logged
+pub struct DeviceCountSample {
+    /// Capture time of the log, since UNIX_EPOCH
+    pub timestamp: Duration,
+    pub device_count: u64,
+}
+
+/// Replays a capture through the same packet handling and cache expiry as the live scanner
+///
+/// Cache cleanup and logging run on the capture's clock: every mac_cache_log_period of capture
+/// time a sample is passed to on_sample, followed by a final sample at the last frame,
+/// like the live scanner flushes on shutdown.
+///
+/// Fails with a config error if the OUI database cannot be read, and with a sink error if the
+/// capture cannot be read.
+pub fn replay_capture<R: Read>(
+    reader: CaptureReader<R>,
+    options: &ScannerOptions,
+    replay: &ReplayOptions,
+    mut on_sample: impl FnMut(DeviceCountSample),
+) -> Result<(), ArpScannerErr> {
+    let log_period = Duration::from_secs(options.mac_cache_log_period.max(1));
+
+    let mut clock = ReplayClock {
+        start: Instant::now(),
+        first_timestamp: None,
+        realtime: replay.realtime,
+    };
+
+    let filter = PresenceFilter {
+        own_mac: replay.own_mac,
+        subnet: replay.subnet,
+        targets: options.targets.clone(),
+        passive_sources: options.passive_sources,
+        announcement_sources: options.announcement_sources,
+        privacy: options.privacy,
+        ndp: options.ndp,
+        ipv6_prefixes: vec![],
+        policy: options.arp_presence_policy,
+    };
+
+    let classifier =
+        DeviceClassifier::load(options.oui_db_path.as_deref(), options.class_rules.clone())
+            .map_err(|error| ConfigErr::OuiDatabase {
+                path: PathBuf::from(options.oui_db_path.clone().unwrap_or_default()),
+                error,
+            })?;
+    // replayed pseudonyms only need to be stable within the replay
+    let pseudonymizer = if options.privacy {
+        Some(Pseudonymizer::new(None))
+    } else {
+        None
+    };
+    let registry = DeviceRegistry::new(classifier, pseudonymizer);
+    let dedup_window = options.randomized_dedup_window.map(Duration::from_secs);
+    let mut cache = MacCache::new();
+    let mut now = Duration::ZERO;
+    let mut next_clean = JANITOR_PERIOD;
+    let mut next_log = log_period;
+
+    for frame in reader {
+        let frame = frame.map_err(SinkErr::ReadCapture)?;
+
+        // frames are not guaranteed to be ordered, time never goes backwards
+        now = now.max(clock.offset(frame.timestamp));
+
+        // run every janitor and log tick that happened before this frame
+        while next_clean.min(next_log) <= now {
+            if next_clean <= next_log {
+                clock.wait_until(next_clean);
+
+                for (mac, entry) in
+                    cache.remove_expired(clock.instant(next_clean), options.mac_addr_timeout)
+                {
+                    log!(
+                        log::Level::Trace,
+                        "deleting mac: {}, present for {}s",
+                        mac,
+                        entry.dwell_time().as_secs()
+                    );
+                }
+
+                next_clean += JANITOR_PERIOD;
+            } else {
+                clock.wait_until(next_log);
+
+                on_sample(DeviceCountSample {
+                    timestamp: clock.timestamp(next_log),
+                    device_count: options.exclusions.tally(&cache, dedup_window).total,
+                });
+
+                next_log += log_period;
+            }
+        }
+
+        clock.wait_until(now);
+
+        let wall_time = UNIX_EPOCH + clock.timestamp(now);
+
+        match filter.observe(&frame.data) {
+            Some(DeviceEvent::Present(presence)) => {
+                let sighting = Sighting {
+                    ip: presence.ip,
+                    interface: String::from(REPLAY_INTERFACE),
+                    at: clock.instant(now),
+                    wall_time,
+                    details: DeviceDetails::default(),
+                };
+                registry.record(&mut cache, presence, sighting);
+            }
+            Some(DeviceEvent::Departed(mac)) => registry.depart(&mut cache, &mac, wall_time),
+            None => {}
+        }
+
+        if let Some((mac, details)) = filter.check_announcement(&frame.data) {
+            registry.enrich(&mut cache, &mac, details, wall_time);
+        }
+    }
+
+    if clock.first_timestamp.is_some() {
+        on_sample(DeviceCountSample {
+            timestamp: clock.timestamp(now),
+            device_count: options.exclusions.tally(&cache, dedup_window).total,
+        });
+    }
+
+    Ok(())
+}
+
+// Maps capture timestamps to offsets from the first frame and instants
+struct ReplayClock {
+    start: Instant,
+    first_timestamp: Option<Duration>,
+    realtime: bool,
+}
+
+impl ReplayClock {
+    fn offset(&mut self, timestamp: Duration) -> Duration {
+        let first = *self.first_timestamp.get_or_insert(timestamp);
+        timestamp.saturating_sub(first)
+    }
+
+    fn instant(&self, offset: Duration) -> Instant {
+        self.start + offset
+    }
+
+    fn timestamp(&self, offset: Duration) -> Duration {
+        self.first_timestamp.unwrap_or_default() + offset
+    }
+
+    fn wait_until(&self, offset: Duration) {
+        if !self.realtime {
+            return;
+        }
+
+        let elapsed = self.start.elapsed();
+        if offset > elapsed {
+            thread::sleep(offset - elapsed);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use pnet_datalink::MacAddr;
+
+use crate::cache::MacCache;
+
+/// Decides when cached devices are probed again before they expire
+///
+/// A device that has been silent for timeout - retries * interval gets up to retries probes,
+/// interval apart, so a single missed reply does not make it leave. Any sighting of the device
+/// resets its retries, but probes stay at least interval apart.
+pub struct ReprobeSchedule {
+    retries: u32,
+    interval: Duration,
+    timeout: Duration,
+    probes: HashMap<MacAddr, ProbeState>,
+}
+
+// Probes sent since the device was last seen
+struct ProbeState {
+    seen_at: Instant,
+    sent: u32,
+    last_sent: Option<Instant>,
+}
+
+impl ReprobeSchedule {
+    /// Creates a schedule for devices expiring after timeout; no device is probed if retries is 0
+    ///
+    /// Retries are limited to [`max_retries`], so devices are not probed as soon as they are seen.
+    pub fn new(retries: u32, interval: Duration, timeout: Duration) -> Self {
+        Self {
+            retries: retries.min(max_retries(interval, timeout)),
+            interval,
+            timeout,
+            probes: HashMap::new(),
+        }
+    }
+
+    /// Whether known devices are probed individually
+    pub fn is_enabled(&self) -> bool {
+        self.retries > 0
+    }
+
+    /// Returns the devices, with their current ip, that should be probed at now
+    pub fn due(&mut self, cache: &MacCache, now: Instant) -> Vec<(MacAddr, Ipv4Addr)> {
+        if !self.is_enabled() {
+            return vec![];
+        }
+
+        // forget devices that expired or were removed
+        self.probes.retain(|mac, _| cache.get(mac).is_some());
+
+        let window = self.timeout.saturating_sub(self.interval * self.retries);
+        let mut due = vec![];
+
+        for (mac, entry) in cache.iter() {
+            let ip = match entry.ip() {
+                Some(ip) => ip,
+                None => continue,
+            };
+
+            if now.saturating_duration_since(entry.last_seen_at) < window {
+                continue;
+            }
+
+            let state = self.probes.entry(*mac).or_insert(ProbeState {
+                seen_at: entry.last_seen_at,
+                sent: 0,
+                last_sent: None,
+            });
+
+            // device answered since the last probe
+            if state.seen_at != entry.last_seen_at {
+                state.seen_at = entry.last_seen_at;
+                state.sent = 0;
+            }
+
+            let is_due = state.last_sent.map_or(true, |last_sent| {
+                now.saturating_duration_since(last_sent) >= self.interval
+            });
+
+            if state.sent < self.retries && is_due {
+                state.sent += 1;
+                state.last_sent = Some(now);
+                due.push((*mac, ip));
+            }
+        }
+
+        due
+    }
+}
+
+/// Most retries, interval apart, that start after a device has been silent for some time
+///
+/// With more, the device would be probed before it has been silent at all.
+pub fn max_retries(interval: Duration, timeout: Duration) -> u32 {
+    let interval = interval.as_millis().max(1);
+    let retries = timeout.as_millis().saturating_sub(1) / interval;
+    u32::try_from(retries).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::SystemTime};
+
+    use super::*;
+    use crate::cache::{DeviceDetails, Sighting};
+
+    const MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 1);
+    const IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    fn see(cache: &mut MacCache, at: Instant) {
+        cache.record(
+            MAC,
+            Sighting {
+                ip: Some(IpAddr::V4(IP)),
+                interface: String::from("eth0"),
+                at,
+                wall_time: SystemTime::now(),
+                details: DeviceDetails::default(),
+            },
+        );
+    }
+
+    /// Seconds after start at which the device is probed, checking every second
+    fn probes(
+        schedule: &mut ReprobeSchedule,
+        cache: &MacCache,
+        start: Instant,
+        until: u64,
+    ) -> Vec<u64> {
+        (0..=until)
+            .filter(|s| !schedule.due(cache, start + secs(*s as f64)).is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn silent_device_is_probed_retries_times_interval_apart() {
+        let start = Instant::now();
+        let mut cache = MacCache::new();
+        see(&mut cache, start);
+
+        let mut schedule = ReprobeSchedule::new(3, secs(5.0), secs(30.0));
+        assert_eq!(probes(&mut schedule, &cache, start, 30), vec![15, 20, 25]);
+    }
+
+    #[test]
+    fn sighting_does_not_make_device_due_before_interval() {
+        let start = Instant::now();
+        let mut cache = MacCache::new();
+        see(&mut cache, start);
+
+        // probing starts after 1s of silence
+        let mut schedule = ReprobeSchedule::new(3, secs(5.0), secs(16.0));
+        assert_eq!(schedule.due(&cache, start + secs(1.0)), vec![(MAC, IP)]);
+
+        // the reply resets the retries, the next probe still waits for the interval
+        see(&mut cache, start + secs(1.5));
+        for at in [2.5, 3.0, 5.5] {
+            assert!(schedule.due(&cache, start + secs(at)).is_empty(), "{at}");
+        }
+        assert_eq!(schedule.due(&cache, start + secs(6.0)), vec![(MAC, IP)]);
+    }
+
+    #[test]
+    fn retries_that_do_not_fit_in_timeout_are_limited() {
+        assert_eq!(max_retries(secs(5.0), secs(300.0)), 59);
+        assert_eq!(max_retries(secs(5.0), secs(16.0)), 3);
+        assert_eq!(max_retries(secs(5.0), secs(15.0)), 2);
+        assert_eq!(max_retries(secs(5.0), secs(5.0)), 0);
+        assert_eq!(max_retries(Duration::ZERO, secs(5.0)), 4999);
+
+        let start = Instant::now();
+        let mut cache = MacCache::new();
+        see(&mut cache, start);
+
+        // 3 * 5s would cover the whole timeout, probing the device as soon as it was seen
+        let mut schedule = ReprobeSchedule::new(3, secs(5.0), secs(10.0));
+        assert_eq!(probes(&mut schedule, &cache, start, 10), vec![5]);
+
+        assert!(!ReprobeSchedule::new(3, secs(10.0), secs(10.0)).is_enabled());
+    }
+}
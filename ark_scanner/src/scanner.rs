@@ -2,207 +2,1278 @@
 // - http://www.cs.newpaltz.edu/~easwaran/CCN/Week13/ARP.pdf
 // - https://www.sciencedirect.com/topics/computer-science/address-resolution-protocol-request#:~:text=ARP%20Packets,same%20way%20as%20IP%20packets
 
-use std::net::Ipv4Addr;
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 use std::{net::IpAddr, thread};
 
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
 use log::log;
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
-use pnet_datalink::{Channel, DataLinkReceiver, DataLinkSender, MacAddr, NetworkInterface};
+use pnet::packet::ethernet::EthernetPacket;
+use pnet_datalink::{MacAddr, NetworkInterface};
+use rand::{seq::SliceRandom, Rng};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 
-use crate::cache::MacCache;
-use crate::cache_logger::{CacheLogger, Logger};
-use crate::config::ScannerOptions;
-use crate::error::{ArpScannerErr, InterfaceErr};
+use crate::cache::{MacCache, Sighting};
+use crate::cache_logger::{CacheLogger, DeviceCounts, Logger};
+use crate::capture::{CaptureSink, CaptureWriter};
+use crate::check::ensure_valid;
+use crate::classify::DeviceClassifier;
+use crate::config::{ScanMode, ScannerOptions};
+use crate::error::{ArpScannerErr, ConfigErr, InterfaceErr, SinkErr};
+use crate::ndp::{gen_all_nodes_echo_request, gen_neighbor_solicitation};
 use crate::network::{
-    compute_subnet_ips, gen_arp_request, is_interface_connected, select_default_interface,
-    NetworkCommandLimiter,
+    gen_arp_request, gen_unicast_arp_request, select_default_interface, validate_interface,
+    InterfaceSelector, NetworkCommandLimiter,
+};
+use crate::pacing::Pacer;
+use crate::presence::{DeviceEvent, PresenceFilter};
+use crate::privacy::{shown, Pseudonymizer};
+use crate::registry::DeviceRegistry;
+use crate::reload::{LiveOptions, Reloader};
+use crate::reprobe::{self, ReprobeSchedule};
+use crate::secret::Secret;
+use crate::shutdown::Shutdown;
+use crate::sync::lock;
+use crate::transport::{
+    PacketReceiver, PacketSender, PacketTransport, PnetTransport, RECEIVE_TIMEOUT,
 };
 
-pub fn init_arp_scanner(options: ScannerOptions) -> Result<(), ArpScannerErr> {
-    let interfaces = pnet_datalink::interfaces();
+/// Interval at which expired macs are removed from the cache
+pub(crate) const JANITOR_PERIOD: Duration = Duration::from_secs(5);
+// Interval at which cached devices are checked for re-probing
+const REPROBE_CHECK_PERIOD: Duration = Duration::from_secs(1);
+// Interval at which signals, and watched config files when reloading is enabled, are checked
+const SIGNAL_POLL_PERIOD: Duration = Duration::from_millis(500);
+
+/// Handle to a running scanner
+///
+/// Dropping the handle without calling [`ScannerHandle::stop`] leaves the scanner running
+/// in the background.
+pub struct ScannerHandle {
+    shutdown: Arc<Shutdown>,
+    threads: Vec<JoinHandle<()>>,
+    options: Arc<LiveOptions>,
+    counted: Vec<CountedInterface>,
+}
+
+impl ScannerHandle {
+    /// Stops all scanner threads and waits for them to exit
+    ///
+    /// The final device count is flushed to the cache logger before returning. Returns the error
+    /// the scanner stopped with if it was not stopped on request, such as a closed channel.
+    pub fn stop(self) -> Result<(), ArpScannerErr> {
+        self.shutdown.trigger();
+
+        for thread in self.threads {
+            if thread.join().is_err() {
+                log!(log::Level::Error, "scanner thread panicked before shutdown");
+            }
+        }
+
+        match self.shutdown.take_failure() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether a stop has been requested, or the scanner stopped because of an error
+    pub fn is_stopped(&self) -> bool {
+        self.shutdown.is_stopped()
+    }
+
+    /// Applies reloaded options to the running scanner, keeping the contents of its caches
+    ///
+    /// Location, timing, sinks, targets, detection and exclusion changes are picked up by the
+    /// threads at their next period. Returns the keys of changed options that need a restart,
+    /// such as interfaces, mode or capture, which keep their current value. Options with errors
+    /// are rejected and nothing is applied.
+    pub fn reload(&self, options: ScannerOptions) -> Result<Vec<&'static str>, ConfigErr> {
+        self.options.reload(options)
+    }
+
+    /// Current device count of each interface, with the location it is logged as
+    ///
+    /// Counts are tallied like the periodic ones sent to the cache logger.
+    pub fn device_counts(&self) -> Vec<(String, DeviceCounts)> {
+        let options = self.options.get();
 
-    let interface = match select_default_interface(&interfaces) {
-        Some(interface) => interface,
-        _ => return Err(ArpScannerErr::InterfaceError(InterfaceErr::NotFound)),
+        self.counted
+            .iter()
+            .map(|counted| {
+                (
+                    logged_location(&counted.location, counted.follows_location, &options),
+                    tally(&counted.mac_cache, &options),
+                )
+            })
+            .collect()
+    }
+}
+
+// Cache of an interface, as counted under its location
+struct CountedInterface {
+    location: String,
+    follows_location: bool,
+    mac_cache: Arc<Mutex<MacCache>>,
+}
+
+/// Runs the ARP scanner on the configured (or default) network interfaces until SIGINT or SIGTERM
+/// is received
+///
+/// Sends ARP requests to every ip in each interface subnet, caches responding mac addresses and
+/// periodically logs the number of cached devices per interface. Returns early with an error if
+/// the scanner stops by itself, e.g. when the channel of an interface is closed.
+pub fn init_arp_scanner(options: ScannerOptions) -> Result<(), ArpScannerErr> {
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(e) => return Err(ArpScannerErr::Signal(e)),
     };
 
-    let source_mac = match interface.mac {
-        Some(mac) => mac,
-        None => return Err(ArpScannerErr::InterfaceError(InterfaceErr::NoMac)),
+    let handle = start_arp_scanner(options)?;
+
+    // polled so a failed scanner is noticed in between signals
+    while !handle.shutdown.wait(SIGNAL_POLL_PERIOD) {
+        if let Some(signal) = signals.pending().next() {
+            log!(
+                log::Level::Info,
+                "received signal {}, stopping scanner...",
+                signal
+            );
+            break;
+        }
+    }
+
+    handle.stop()
+}
+
+/// Runs the ARP scanner like [`init_arp_scanner`], reloading its options through reloader on
+/// SIGHUP or when one of its watched files is modified
+///
+/// Reloaded options with errors are rejected and the scanner keeps running with its current
+/// ones, see [`ScannerHandle::reload`] for what is applied.
+pub fn init_arp_scanner_with_reload(
+    options: ScannerOptions,
+    mut reloader: Reloader,
+) -> Result<(), ArpScannerErr> {
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => return Err(ArpScannerErr::Signal(e)),
     };
 
-    let network = match interface.ips.iter().find(|ip| ip.is_ipv4()) {
-        Some(net) => net,
-        None => return Err(ArpScannerErr::InterfaceError(InterfaceErr::NoIpv4)),
+    let handle = start_arp_scanner(options)?;
+
+    // polled so watched files and failures are checked in between signals
+    while !handle.shutdown.wait(SIGNAL_POLL_PERIOD) {
+        let mut reload = false;
+
+        for signal in signals.pending() {
+            if signal == SIGHUP {
+                log!(log::Level::Info, "received SIGHUP, reloading options...");
+                reload = true;
+            } else {
+                log!(
+                    log::Level::Info,
+                    "received signal {}, stopping scanner...",
+                    signal
+                );
+                return handle.stop();
+            }
+        }
+
+        if reloader.is_modified() || reload {
+            reload_options(&handle, &mut reloader);
+        }
+    }
+
+    handle.stop()
+}
+
+fn reload_options(handle: &ScannerHandle, reloader: &mut Reloader) {
+    let options = match reloader.load() {
+        Ok(options) => options,
+        Err(e) => {
+            log!(
+                log::Level::Error,
+                "unable to reload options, keeping the current ones: {}",
+                e
+            );
+            return;
+        }
     };
 
-    let source_ip = match network.ip() {
-        IpAddr::V4(addr) => addr,
-        _ => return Err(ArpScannerErr::InterfaceError(InterfaceErr::NoIpv4)),
+    let deferred = match handle.reload(options) {
+        Ok(deferred) => deferred,
+        Err(e) => {
+            log!(
+                log::Level::Error,
+                "rejected reloaded options, keeping the current ones: {}",
+                e
+            );
+            return;
+        }
     };
+    log!(log::Level::Info, "reloaded options");
+
+    if !deferred.is_empty() {
+        log!(
+            log::Level::Warn,
+            "changes to {} only take effect after a restart",
+            deferred.join(", ")
+        );
+    }
+}
+
+/// Starts the ARP scanner in background threads, on the configured interfaces or the default one
+///
+/// Unlike [`init_arp_scanner`], no signal handlers are installed; the caller stops the scanner
+/// through the returned handle.
+pub fn start_arp_scanner(options: ScannerOptions) -> Result<ScannerHandle, ArpScannerErr> {
+    let interfaces = pnet_datalink::interfaces();
+
+    let scanned = if options.interfaces.is_empty() {
+        let interface = match select_default_interface(&interfaces) {
+            Some(interface) => interface,
+            _ => return Err(ArpScannerErr::Interface(InterfaceErr::NotFound)),
+        };
+
+        log!(
+            log::Level::Info,
+            "no interface configured, selected {} by default",
+            interface.name
+        );
 
-    let subnet_mask = match network.mask() {
-        IpAddr::V4(addr) => addr,
-        _ => return Err(ArpScannerErr::InterfaceError(InterfaceErr::InvalidMask)),
+        vec![ScannedInterface::new(
+            Arc::new(PnetTransport::new(interface)),
+            options.location.clone(),
+        )]
+    } else {
+        let mut scanned = vec![];
+
+        for configured in &options.interfaces {
+            let interface = select_interface(&interfaces, &configured.selector, options.ndp)?;
+
+            scanned.push(ScannedInterface::new(
+                Arc::new(PnetTransport::new(interface)),
+                configured
+                    .location
+                    .clone()
+                    .unwrap_or_else(|| options.location.clone()),
+            ));
+        }
+
+        scanned
     };
 
-    let ips = compute_subnet_ips(source_ip, subnet_mask);
+    start_arp_scanner_with_interfaces(options, scanned)
+}
 
-    log::log!(
-        log::Level::Info,
-        "Selected interface {}, ip: {}, subnet mask: {}, subnet ip range: {}-{}, logging to {}",
-        interface.name,
-        source_ip,
-        subnet_mask,
-        ips.first().unwrap(),
-        ips.last().unwrap(),
-        options
-            .log_api_url
-            .clone()
-            .unwrap_or_else(|| String::from("local environment"))
-    );
+// Picks the first interface matched by selector that can be scanned
+// A selector can match several interfaces, e.g. VLAN sub-interfaces share the mac of their parent.
+pub(crate) fn select_interface(
+    interfaces: &[NetworkInterface],
+    selector: &InterfaceSelector,
+    ndp: bool,
+) -> Result<NetworkInterface, ArpScannerErr> {
+    let mut rejection = None;
+
+    for interface in interfaces.iter().filter(|i| selector.matches(i)) {
+        match validate_interface(interface, ndp) {
+            Ok(()) => return Ok(interface.clone()),
+            Err(reason) => {
+                log!(
+                    log::Level::Warn,
+                    "interface {} matching {} {}",
+                    interface.name,
+                    selector,
+                    reason
+                );
+                rejection.get_or_insert(ArpScannerErr::InterfaceRejected {
+                    interface: interface.name.clone(),
+                    reason,
+                });
+            }
+        }
+    }
+
+    Err(rejection.unwrap_or(ArpScannerErr::InterfaceRejected {
+        interface: selector.to_string(),
+        reason: InterfaceErr::NotFound,
+    }))
+}
+
+/// Starts the ARP scanner in background threads, sending and receiving frames through transport
+///
+/// Use [`crate::simulation::SimulatedLan`] as transport to run the scanner without a real
+/// network interface.
+pub fn start_arp_scanner_with_transport(
+    options: ScannerOptions,
+    transport: Arc<dyn PacketTransport>,
+) -> Result<ScannerHandle, ArpScannerErr> {
+    let location = options.location.clone();
+    start_arp_scanner_with_interfaces(options, vec![ScannedInterface::new(transport, location)])
+}
+
+/// Interface scanned by its own pipeline, its device count is logged as location
+pub struct ScannedInterface {
+    pub transport: Arc<dyn PacketTransport>,
+    pub location: String,
+}
+
+impl ScannedInterface {
+    pub fn new(transport: Arc<dyn PacketTransport>, location: String) -> Self {
+        Self {
+            transport,
+            location,
+        }
+    }
+}
+
+/// Starts the ARP scanner in background threads with independent send, receive, cache and
+/// logging threads per interface
+///
+/// Nothing is started if any of the interfaces cannot be scanned.
+pub fn start_arp_scanner_with_interfaces(
+    options: ScannerOptions,
+    interfaces: Vec<ScannedInterface>,
+) -> Result<ScannerHandle, ArpScannerErr> {
+    if interfaces.is_empty() {
+        return Err(ArpScannerErr::Interface(InterfaceErr::NotFound));
+    }
+
+    // the same options would be rejected on reload
+    ensure_valid(&options)?;
+
+    let classifier =
+        match DeviceClassifier::load(options.oui_db_path.as_deref(), options.class_rules.clone()) {
+            Ok(classifier) => classifier,
+            Err(error) => {
+                return Err(ConfigErr::OuiDatabase {
+                    path: options.oui_db_path.clone().unwrap_or_default().into(),
+                    error,
+                }
+                .into())
+            }
+        };
+
+    let pseudonymizer = if options.privacy {
+        Some(Pseudonymizer::new(
+            options.privacy_key.as_ref().map(Secret::expose),
+        ))
+    } else {
+        None
+    };
+    let registry = Arc::new(DeviceRegistry::new(classifier, pseudonymizer));
+
+    let mut pipelines = vec![];
+    for scanned in interfaces {
+        pipelines.push(Pipeline::new(&options, &registry, scanned)?);
+    }
 
     // Disallows concurrent running of command
-    let reconnect_limiter = NetworkCommandLimiter::new(&options.reconnect_cmd);
-
-    let (tx, rx) = match pnet_datalink::channel(
-        &interface,
-        pnet_datalink::Config {
-            ..pnet_datalink::Config::default()
-        },
-    ) {
-        Ok(channel) => match channel {
-            Channel::Ethernet(tx, rx) => (tx, rx),
-            // this should never happen
-            _ => return Err(ArpScannerErr::OpenChannelError(std::io::ErrorKind::Other)),
-        },
-        Err(e) => return Err(ArpScannerErr::OpenChannelError(e.kind())),
+    let reconnect_limiter = Arc::new(NetworkCommandLimiter::new(&options.reconnect_cmd)?);
+
+    let capture = match &options.capture {
+        Some(capture) => {
+            let interfaces: Vec<NetworkInterface> = pipelines
+                .iter()
+                .map(|pipeline| pipeline.transport.interface().clone())
+                .collect();
+
+            match CaptureWriter::create(
+                Path::new(&capture.path),
+                &interfaces,
+                capture.rotate_bytes,
+                capture.max_files,
+            ) {
+                Ok(writer) => {
+                    log!(
+                        log::Level::Info,
+                        "recording arp traffic to {}",
+                        capture.path
+                    );
+                    Some(Arc::new(CaptureSink::new(writer)))
+                }
+                Err(error) => {
+                    return Err(SinkErr::Capture {
+                        path: PathBuf::from(&capture.path),
+                        error,
+                    }
+                    .into())
+                }
+            }
+        }
+        None => None,
     };
 
-    let mac_cache = Arc::new(Mutex::new(MacCache::new()));
+    let mut channels = vec![];
+    for pipeline in &pipelines {
+        match pipeline.transport.open() {
+            Ok(channel) => channels.push(channel),
+            Err(error) => {
+                return Err(ArpScannerErr::Channel {
+                    interface: pipeline.transport.interface().name.clone(),
+                    error,
+                })
+            }
+        }
+    }
+
+    let options = Arc::new(LiveOptions::new(options));
+    let shutdown = Arc::new(Shutdown::new());
+
+    let mut threads = vec![];
+    let mut counted = vec![];
+    for (interface_id, (pipeline, (tx, rx))) in pipelines.into_iter().zip(channels).enumerate() {
+        let capture = capture.clone().map(|sink| CaptureTap {
+            sink,
+            interface_id: interface_id as u32,
+        });
 
-    thread::scope(|s| {
-        s.spawn(|| clean_mac_cache_periodic(Arc::clone(&mac_cache), &options));
-        s.spawn(|| receive_arp_packets_constant(rx, Arc::clone(&mac_cache), &source_mac));
-        s.spawn(|| {
-            send_arp_req_to_ips_periodic(tx, ips, &interface, &source_mac, &source_ip, &options)
+        counted.push(CountedInterface {
+            location: pipeline.location.clone(),
+            follows_location: pipeline.follows_location,
+            mac_cache: Arc::clone(&pipeline.mac_cache),
         });
-        s.spawn(|| log_mac_cache_periodic(Arc::clone(&mac_cache), &options, &reconnect_limiter));
-        s.spawn(|| check_interface_connectivity(&interface, &reconnect_limiter));
-    });
+        threads.extend(pipeline.spawn(tx, rx, &options, capture, &reconnect_limiter, &shutdown));
+    }
 
-    Ok(())
+    Ok(ScannerHandle {
+        shutdown,
+        threads,
+        options,
+        counted,
+    })
 }
 
-fn clean_mac_cache_periodic(mac_cache: Arc<Mutex<MacCache>>, options: &ScannerOptions) {
-    loop {
-        thread::sleep(Duration::from_secs(5));
+// Everything needed to scan one interface
+struct Pipeline {
+    transport: Arc<dyn PacketTransport>,
+    location: String,
+    /// Whether the interface is logged as the scanner location, and follows it on reload
+    follows_location: bool,
+    source: SourceAddrs,
+    filter: PresenceFilter,
+    registry: Arc<DeviceRegistry>,
+    mac_cache: Arc<Mutex<MacCache>>,
+}
 
-        let mut macs_to_remove: Vec<MacAddr> = vec![];
+impl Pipeline {
+    fn new(
+        options: &ScannerOptions,
+        registry: &Arc<DeviceRegistry>,
+        scanned: ScannedInterface,
+    ) -> Result<Self, ArpScannerErr> {
+        let interface = scanned.transport.interface();
 
-        let mut cache = mac_cache.lock().unwrap();
+        if let Err(reason) = validate_interface(interface, options.ndp) {
+            return Err(rejected(interface, reason));
+        }
 
-        log!(log::Level::Trace, "running cache janitor...");
-        for (mac, instant) in cache.iter() {
-            if instant.elapsed().as_secs() > options.mac_addr_timeout {
-                macs_to_remove.push(*mac);
-            }
+        let source_mac = match interface.mac {
+            Some(mac) => mac,
+            None => return Err(rejected(interface, InterfaceErr::NoMac)),
+        };
+
+        let ipv4 = interface
+            .ips
+            .iter()
+            .find_map(|network| match (network.ip(), network.mask()) {
+                (IpAddr::V4(ip), IpAddr::V4(mask)) => Some((ip, mask)),
+                _ => None,
+            });
+
+        let ipv6_prefixes: Vec<Ipv6Network> = interface
+            .ips
+            .iter()
+            .filter_map(|network| match network {
+                IpNetwork::V6(network) => Some(*network),
+                _ => None,
+            })
+            .collect();
+
+        // solicitations are sent from the link-local address when there is one
+        let source_ipv6 = match ipv6_prefixes
+            .iter()
+            .map(|network| network.ip())
+            .find(|ip| ip.segments()[0] & 0xffc0 == 0xfe80)
+            .or_else(|| ipv6_prefixes.first().map(|network| network.ip()))
+        {
+            Some(ip) if options.ndp => Some(ip),
+            _ => None,
+        };
+
+        // IPv6-only interfaces can still be scanned with NDP
+        let (source_ip, subnet) = match ipv4 {
+            Some((ip, mask)) => match Ipv4Network::with_netmask(ip, mask) {
+                Ok(subnet) => (ip, Some(subnet)),
+                Err(_) => return Err(rejected(interface, InterfaceErr::InvalidMask)),
+            },
+            None if source_ipv6.is_some() => (Ipv4Addr::UNSPECIFIED, None),
+            None => return Err(rejected(interface, InterfaceErr::NoIpv4)),
+        };
+
+        let ips = targets_within(subnet, options);
+
+        log::log!(
+            log::Level::Info,
+            "Selected interface {} at {} in {} mode, ip: {}, subnet mask: {}, scanned ip range: {} ({} ips), ndp source ip: {}, logging to {}",
+            interface.name,
+            scanned.location,
+            options.mode,
+            source_ip,
+            subnet.map_or(Ipv4Addr::UNSPECIFIED, |subnet| subnet.mask()),
+            match (ips.first(), ips.last()) {
+                (Some(first), Some(last)) => format!("{first}-{last}"),
+                _ => String::from("none"),
+            },
+            ips.len(),
+            source_ipv6.map_or_else(|| String::from("disabled"), |ip| ip.to_string()),
+            options
+                .log_api_url
+                .clone()
+                .unwrap_or_else(|| String::from("local environment"))
+        );
+
+        Ok(Self {
+            follows_location: scanned.location == options.location,
+            location: scanned.location,
+            source: SourceAddrs {
+                mac: source_mac,
+                ip: source_ip,
+                ipv6: source_ipv6,
+            },
+            filter: PresenceFilter {
+                own_mac: Some(source_mac),
+                subnet,
+                targets: options.targets.clone(),
+                ndp: options.ndp,
+                ipv6_prefixes,
+                policy: options.arp_presence_policy,
+                passive_sources: options.passive_sources,
+                announcement_sources: options.announcement_sources,
+                privacy: options.privacy,
+            },
+            registry: Arc::clone(registry),
+            mac_cache: Arc::new(Mutex::new(MacCache::new())),
+            transport: scanned.transport,
+        })
+    }
+
+    fn spawn(
+        self,
+        tx: Box<dyn PacketSender>,
+        rx: Box<dyn PacketReceiver>,
+        options: &Arc<LiveOptions>,
+        capture: Option<CaptureTap>,
+        reconnect_limiter: &Arc<NetworkCommandLimiter>,
+        shutdown: &Arc<Shutdown>,
+    ) -> Vec<JoinHandle<()>> {
+        let Pipeline {
+            transport,
+            location,
+            follows_location,
+            source,
+            filter,
+            registry,
+            mac_cache,
+        } = self;
+
+        let interface_name = transport.interface().name.clone();
+        let subnet = filter.subnet;
+        let capture = capture.map(Arc::new);
+
+        // passive pipelines only listen, their sender is kept open as long as they do since
+        // closing it can close the channel
+        let (tx, idle_tx) = match options.get().mode {
+            ScanMode::Active => (Some(tx), None),
+            ScanMode::Passive => (None, Some(tx)),
+        };
+
+        let mut threads = vec![
+            {
+                let (mac_cache, options, shutdown) = (
+                    Arc::clone(&mac_cache),
+                    Arc::clone(options),
+                    Arc::clone(shutdown),
+                );
+                thread::spawn(move || clean_mac_cache_periodic(mac_cache, &options, &shutdown))
+            },
+            {
+                let (mac_cache, capture, shutdown) = (
+                    Arc::clone(&mac_cache),
+                    capture.clone(),
+                    Arc::clone(shutdown),
+                );
+                let options = Arc::clone(options);
+                thread::spawn(move || {
+                    let _idle_tx = idle_tx;
+                    receive_arp_packets_constant(
+                        rx,
+                        mac_cache,
+                        &interface_name,
+                        LiveFilter::new(filter, options),
+                        &registry,
+                        capture.as_deref(),
+                        &shutdown,
+                    )
+                })
+            },
+            {
+                let (mac_cache, options, reconnect_limiter, shutdown) = (
+                    Arc::clone(&mac_cache),
+                    Arc::clone(options),
+                    Arc::clone(reconnect_limiter),
+                    Arc::clone(shutdown),
+                );
+                thread::spawn(move || {
+                    log_mac_cache_periodic(
+                        mac_cache,
+                        &location,
+                        follows_location,
+                        &options,
+                        reconnect_limiter,
+                        &shutdown,
+                    )
+                })
+            },
+            {
+                let (reconnect_limiter, shutdown) =
+                    (Arc::clone(reconnect_limiter), Arc::clone(shutdown));
+                thread::spawn(move || {
+                    check_interface_connectivity(transport, &reconnect_limiter, &shutdown)
+                })
+            },
+        ];
+
+        if let Some(tx) = tx {
+            threads.push({
+                let (mac_cache, options, shutdown) = (
+                    Arc::clone(&mac_cache),
+                    Arc::clone(options),
+                    Arc::clone(shutdown),
+                );
+                thread::spawn(move || {
+                    send_arp_req_to_ips_periodic(
+                        tx,
+                        subnet,
+                        mac_cache,
+                        &source,
+                        &options,
+                        capture.as_deref(),
+                        &shutdown,
+                    )
+                })
+            });
         }
 
-        for mac in macs_to_remove {
-            log!(log::Level::Trace, "deleting mac: {}", mac);
-            cache.delete(&mac);
+        threads
+    }
+}
+
+fn rejected(interface: &NetworkInterface, reason: InterfaceErr) -> ArpScannerErr {
+    ArpScannerErr::InterfaceRejected {
+        interface: interface.name.clone(),
+        reason,
+    }
+}
+
+// Records frames of one interface to the shared capture file
+struct CaptureTap {
+    sink: Arc<CaptureSink>,
+    interface_id: u32,
+}
+
+impl CaptureTap {
+    fn record(&self, frame: &[u8]) {
+        self.sink.record(self.interface_id, frame);
+    }
+}
+
+fn clean_mac_cache_periodic(
+    mac_cache: Arc<Mutex<MacCache>>,
+    options: &LiveOptions,
+    shutdown: &Shutdown,
+) {
+    while !shutdown.wait(JANITOR_PERIOD) {
+        let timeout = options.get().mac_addr_timeout;
+        let mut cache = lock(&mac_cache);
+
+        log!(log::Level::Trace, "running cache janitor...");
+        for (mac, entry) in cache.remove_expired(Instant::now(), timeout) {
+            log!(
+                log::Level::Trace,
+                "deleting mac: {}, present for {}s",
+                mac,
+                entry.dwell_time().as_secs()
+            );
         }
     }
 }
 
+// Reloaded options are picked up at the end of the current period
 fn log_mac_cache_periodic(
     mac_cache: Arc<Mutex<MacCache>>,
-    options: &ScannerOptions,
-    reconnect_limiter: &NetworkCommandLimiter,
+    location: &str,
+    follows_location: bool,
+    options: &LiveOptions,
+    reconnect_limiter: Arc<NetworkCommandLimiter>,
+    shutdown: &Shutdown,
 ) {
-    let mut logger = CacheLogger::new(
+    let mut current = options.get();
+    let mut logger = cache_logger(&current, &reconnect_limiter);
+
+    while !shutdown.wait(Duration::from_secs(current.mac_cache_log_period)) {
+        let reloaded = options.get();
+
+        if (
+            &reloaded.log_api_url,
+            &reloaded.log_api_key,
+            reloaded.api_retry_limit,
+        ) != (
+            &current.log_api_url,
+            &current.log_api_key,
+            current.api_retry_limit,
+        ) {
+            log!(log::Level::Info, "switching to reloaded log sink");
+            logger = cache_logger(&reloaded, &reconnect_limiter);
+        }
+        current = reloaded;
+
+        let counts = tally(&mac_cache, &current);
+        logger.log(
+            logged_location(location, follows_location, &current),
+            &counts,
+        );
+    }
+
+    // flush the count of the interrupted interval
+    let current = options.get();
+    let location = logged_location(location, follows_location, &current);
+    let counts = tally(&mac_cache, &current);
+    log!(
+        log::Level::Info,
+        "flushing final mac cache size at {}: {}",
+        location,
+        counts.total
+    );
+    logger.log(location, &counts);
+}
+
+fn cache_logger(
+    options: &ScannerOptions,
+    reconnect_limiter: &Arc<NetworkCommandLimiter>,
+) -> CacheLogger<'static> {
+    let reconnect_limiter = Arc::clone(reconnect_limiter);
+
+    CacheLogger::new(
         options.log_api_url.clone(),
         options.log_api_key.clone(),
         options.api_retry_limit,
-        || reconnect_limiter.run(),
-    );
-
-    loop {
-        thread::sleep(Duration::from_secs(options.mac_cache_log_period));
+        move || reconnect_limiter.run(),
+    )
+}
 
-        let cache = mac_cache.lock().unwrap();
-        let cache_size = cache.size();
+// Devices of the cache that are counted
+fn tally(mac_cache: &Mutex<MacCache>, options: &ScannerOptions) -> DeviceCounts {
+    options.exclusions.tally(
+        &lock(mac_cache),
+        options.randomized_dedup_window.map(Duration::from_secs),
+    )
+}
 
-        logger.log(options.location.clone(), cache_size as u64);
+fn logged_location(location: &str, follows_location: bool, options: &ScannerOptions) -> String {
+    if follows_location {
+        options.location.clone()
+    } else {
+        String::from(location)
     }
 }
 
 fn receive_arp_packets_constant(
-    mut rx: Box<dyn DataLinkReceiver>,
+    mut rx: Box<dyn PacketReceiver>,
     mac_cache: Arc<Mutex<MacCache>>,
-    source_mac: &MacAddr,
+    interface_name: &str,
+    mut filter: LiveFilter,
+    registry: &DeviceRegistry,
+    capture: Option<&CaptureTap>,
+    shutdown: &Shutdown,
 ) {
-    loop {
+    while !shutdown.is_stopped() {
         let packet = match rx.next() {
             Ok(buf) => buf,
-            Err(_) => continue,
+            // read timeouts let the loop observe shutdown
+            Err(e) if is_timeout(&e) => continue,
+            Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {
+                shutdown.fail(ArpScannerErr::Transport {
+                    interface: String::from(interface_name),
+                    error,
+                });
+                return;
+            }
+            // e.g. the interface is down until it is reconnected, wait instead of spinning
+            Err(e) => {
+                log!(
+                    log::Level::Trace,
+                    "failed to receive on {}: {}",
+                    interface_name,
+                    e
+                );
+                shutdown.wait(RECEIVE_TIMEOUT);
+                continue;
+            }
         };
 
-        let eth_packet = match EthernetPacket::new(packet) {
-            Some(packet) => packet,
-            None => continue,
-        };
+        let filter = filter.current();
+
+        // own requests are recorded when sent
+        if let Some(capture) = capture {
+            let is_own = filter
+                .own_mac
+                .map_or(false, |mac| is_frame_from(packet, &mac));
+
+            if filter.is_discovery_frame(packet) && !is_own {
+                capture.record(packet);
+            }
+        }
+
+        match filter.observe(packet) {
+            Some(DeviceEvent::Present(presence)) => {
+                let sighting = Sighting::now(presence.ip, interface_name);
+
+                registry.record(&mut lock(&mac_cache), presence, sighting);
+            }
+            Some(DeviceEvent::Departed(mac)) => {
+                registry.depart(&mut lock(&mac_cache), &mac, SystemTime::now());
+            }
+            None => {}
+        }
+
+        if let Some((mac, details)) = filter.check_announcement(packet) {
+            registry.enrich(&mut lock(&mac_cache), &mac, details, SystemTime::now());
+        }
+    }
+}
 
-        let packet_mac = eth_packet.get_source();
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
 
-        // skip if machine pings itself
-        if packet_mac == *source_mac {
-            continue;
+// Presence filter of an interface, updated with reloaded targets and detection options
+struct LiveFilter {
+    filter: PresenceFilter,
+    options: Arc<LiveOptions>,
+    generation: u64,
+}
+
+impl LiveFilter {
+    fn new(filter: PresenceFilter, options: Arc<LiveOptions>) -> Self {
+        let generation = options.generation();
+
+        Self {
+            filter,
+            options,
+            generation,
         }
+    }
+
+    fn current(&mut self) -> &PresenceFilter {
+        if self.options.generation() != self.generation {
+            self.generation = self.options.generation();
 
-        // skip if is not an ARP packet
-        if eth_packet.get_ethertype().0 != EtherTypes::Arp.0 {
-            continue;
+            let options = self.options.get();
+            self.filter.targets = options.targets.clone();
+            self.filter.policy = options.arp_presence_policy;
+            self.filter.passive_sources = options.passive_sources;
+            self.filter.announcement_sources = options.announcement_sources;
         }
 
-        log!(log::Level::Trace, "incoming arp packet mac: {}", packet_mac);
+        &self.filter
+    }
+}
+
+fn is_frame_from(frame: &[u8], mac: &MacAddr) -> bool {
+    match EthernetPacket::new(frame) {
+        Some(eth_packet) => eth_packet.get_source() == *mac,
+        None => false,
+    }
+}
+
+// Addresses requests are sent from
+struct SourceAddrs {
+    mac: MacAddr,
+    ip: Ipv4Addr,
+    ipv6: Option<Ipv6Addr>,
+}
 
-        let mut cache = mac_cache.lock().unwrap();
+// Frame the sender loop sends next
+enum Probe {
+    /// Sweep of an address no cached device uses
+    Arp(Ipv4Addr),
+    /// Unicast liveness check of a cached device about to expire
+    Reprobe(MacAddr, Ipv4Addr),
+    NdpAllNodes,
+    NdpSolicitation(Ipv6Addr),
+}
 
-        cache.add(packet_mac);
+impl Probe {
+    fn frame(&self, source: &SourceAddrs) -> Option<Vec<u8>> {
+        match (self, source.ipv6) {
+            (Probe::Arp(ip), _) => {
+                gen_arp_request(source.mac, source.ip, *ip).map(|frame| frame.to_vec())
+            }
+            (Probe::Reprobe(mac, ip), _) => {
+                gen_unicast_arp_request(source.mac, source.ip, *mac, *ip)
+                    .map(|frame| frame.to_vec())
+            }
+            (Probe::NdpAllNodes, Some(source_ipv6)) => {
+                Some(gen_all_nodes_echo_request(source.mac, source_ipv6))
+            }
+            (Probe::NdpSolicitation(ip), Some(source_ipv6)) => {
+                Some(gen_neighbor_solicitation(source.mac, source_ipv6, *ip))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Probe {
+    /// Description of the probe for logs, addresses are redacted if privacy is enabled
+    fn shown(&self, privacy: bool) -> String {
+        match self {
+            Probe::Arp(ip) => format!("arp request to {}", shown(ip, privacy)),
+            Probe::Reprobe(mac, ip) => format!(
+                "unicast arp re-probe of {} at {}",
+                shown(mac, privacy),
+                shown(ip, privacy)
+            ),
+            Probe::NdpAllNodes => String::from("all-nodes ping"),
+            Probe::NdpSolicitation(ip) => {
+                format!("neighbor solicitation of {}", shown(ip, privacy))
+            }
+        }
     }
 }
 
+// Sweeps of unknown addresses are spread evenly over the sweep period, or take longer when capped
+// by max_pps. Known devices are re-probed shortly before they expire instead of being swept, as
+// fast as max_pps allows. Reloaded options are picked up by the next sweep.
 fn send_arp_req_to_ips_periodic(
-    mut tx: Box<dyn DataLinkSender>,
-    ips: Vec<Ipv4Addr>,
-    interface: &NetworkInterface,
-    source_mac: &MacAddr,
-    source_ip: &Ipv4Addr,
-    options: &ScannerOptions,
+    mut tx: Box<dyn PacketSender>,
+    subnet: Option<Ipv4Network>,
+    mac_cache: Arc<Mutex<MacCache>>,
+    source: &SourceAddrs,
+    live_options: &LiveOptions,
+    capture: Option<&CaptureTap>,
+    shutdown: &Shutdown,
 ) {
+    let mut generation = live_options.generation();
+    let mut options = live_options.get();
+    let mut ips = targets_within(subnet, &options);
+    let mut sweep_period = sweep_period(&options, ips.len());
+    let mut schedule = reprobe_schedule(&options);
+
+    let mut rng = rand::thread_rng();
+    let mut limit = Pacer::for_limit(options.max_pps, options.burst_size);
+    let mut spread = Pacer::new(None, options.burst_size);
+    let mut sweep: VecDeque<Probe> = VecDeque::new();
+    let mut reprobes: VecDeque<Probe> = VecDeque::new();
+    let mut next_sweep = Instant::now();
+    let mut next_reprobe_check = Instant::now();
+
     loop {
-        for ip in &ips {
-            if let Some(arp_request) = gen_arp_request(*source_mac, *source_ip, *ip) {
-                tx.send_to(&arp_request, Some(interface.clone()));
+        let now = Instant::now();
+
+        if sweep.is_empty() && live_options.generation() != generation {
+            generation = live_options.generation();
+            let reloaded = live_options.get();
+
+            ips = targets_within(subnet, &reloaded);
+
+            // probes already sent to known devices are kept unless their schedule changed
+            if (
+                reloaded.reprobe_retries,
+                reloaded.reprobe_interval,
+                reloaded.mac_addr_timeout,
+            ) != (
+                options.reprobe_retries,
+                options.reprobe_interval,
+                options.mac_addr_timeout,
+            ) {
+                schedule = reprobe_schedule(&reloaded);
+            }
+
+            sweep_period = self::sweep_period(&reloaded, ips.len());
+            if (reloaded.max_pps, reloaded.burst_size) != (options.max_pps, options.burst_size) {
+                limit = Pacer::for_limit(reloaded.max_pps, reloaded.burst_size);
             }
+            options = reloaded;
+        }
+
+        // a sweep running late finishes before the next one starts
+        if sweep.is_empty() && now >= next_sweep {
+            sweep = plan_sweep(
+                &ips,
+                &mac_cache,
+                source,
+                &schedule,
+                options.randomize_targets,
+                &mut rng,
+            );
+            spread = Pacer::for_sweep(
+                sweep.len(),
+                sweep_period,
+                options.max_pps,
+                options.burst_size,
+            );
+            next_sweep = now + sweep_period;
         }
 
-        thread::sleep(Duration::from_secs(options.arp_scan_period));
+        if now >= next_reprobe_check {
+            let due = schedule.due(&lock(&mac_cache), now);
+            // cached macs are pseudonyms in privacy mode, requests are broadcast instead
+            reprobes.extend(due.into_iter().map(|(mac, ip)| {
+                if options.privacy {
+                    Probe::Arp(ip)
+                } else {
+                    Probe::Reprobe(mac, ip)
+                }
+            }));
+            next_reprobe_check = now + REPROBE_CHECK_PERIOD;
+        }
+
+        let wait = if reprobes.is_empty() && sweep.is_empty() {
+            next_sweep
+                .min(next_reprobe_check)
+                .saturating_duration_since(now)
+        } else {
+            // re-probes go first and are only held back by max_pps, their devices are about to
+            // expire. Sweep probes also wait for their spread over the sweep period.
+            let mut delay = limit.ready_in(now);
+            if delay.is_zero() && reprobes.is_empty() {
+                delay = spread.ready_in(now);
+            }
+
+            if delay.is_zero() {
+                let probe = reprobes.pop_front().or_else(|| {
+                    spread.take();
+                    sweep.pop_front()
+                });
+                limit.take();
+
+                if let Some(probe) = probe {
+                    send_probe(&mut *tx, &probe, source, capture, options.privacy);
+                }
+
+                Duration::ZERO
+            } else {
+                delay.min(next_reprobe_check.saturating_duration_since(now))
+            }
+        };
+
+        if shutdown.wait(wait) {
+            return;
+        }
+    }
+}
+
+// Period over which targets are swept, warns if max_pps makes a sweep take longer
+fn sweep_period(options: &ScannerOptions, targets: usize) -> Duration {
+    let sweep_period = Duration::from_secs(
+        options
+            .unknown_sweep_period
+            .unwrap_or(options.arp_scan_period),
+    );
+
+    if let Some(max_pps) = options.max_pps {
+        let sweep = Duration::from_secs_f64(targets as f64 / max_pps.max(1) as f64);
+        if sweep > sweep_period {
+            log!(
+                log::Level::Warn,
+                "sending {} arp requests at {} pps takes {}s, longer than the scan period",
+                targets,
+                max_pps,
+                sweep.as_secs()
+            );
+        }
+    }
+
+    sweep_period
+}
+
+fn targets_within(subnet: Option<Ipv4Network>, options: &ScannerOptions) -> Vec<Ipv4Addr> {
+    match subnet {
+        Some(subnet) => options.targets.ips_within(subnet),
+        None => vec![],
+    }
+}
+
+fn reprobe_schedule(options: &ScannerOptions) -> ReprobeSchedule {
+    let interval = Duration::from_secs(options.reprobe_interval);
+    let timeout = Duration::from_secs(options.mac_addr_timeout);
+
+    let max_retries = reprobe::max_retries(interval, timeout);
+    if options.reprobe_retries > max_retries {
+        log!(
+            log::Level::Warn,
+            "{} re-probes {}s apart do not fit in the {}s mac address timeout, sending {}",
+            options.reprobe_retries,
+            options.reprobe_interval,
+            options.mac_addr_timeout,
+            max_retries
+        );
+    }
+
+    ReprobeSchedule::new(options.reprobe_retries, interval, timeout)
+}
+
+// Addresses swept this period: unknown ips in shuffled order, followed by NDP discovery
+fn plan_sweep(
+    ips: &[Ipv4Addr],
+    mac_cache: &Mutex<MacCache>,
+    source: &SourceAddrs,
+    schedule: &ReprobeSchedule,
+    randomize: bool,
+    rng: &mut impl Rng,
+) -> VecDeque<Probe> {
+    let cache = lock(mac_cache);
+
+    // known devices are re-probed on their own schedule
+    let known: HashSet<Ipv4Addr> = if schedule.is_enabled() {
+        cache.iter().filter_map(|(_, entry)| entry.ip()).collect()
+    } else {
+        HashSet::new()
+    };
+
+    let mut targets: Vec<Ipv4Addr> = ips
+        .iter()
+        .filter(|ip| !known.contains(ip))
+        .copied()
+        .collect();
+
+    if randomize {
+        targets.shuffle(rng);
+    }
+
+    let mut probes: VecDeque<Probe> = targets.into_iter().map(Probe::Arp).collect();
+
+    if source.ipv6.is_some() {
+        probes.push_back(Probe::NdpAllNodes);
+        probes.extend(
+            cache
+                .iter()
+                .flat_map(|(_, entry)| entry.ipv6_addresses.clone())
+                .map(Probe::NdpSolicitation),
+        );
+    }
+
+    probes
+}
+
+fn send_probe(
+    tx: &mut dyn PacketSender,
+    probe: &Probe,
+    source: &SourceAddrs,
+    capture: Option<&CaptureTap>,
+    privacy: bool,
+) {
+    let frame = match probe.frame(source) {
+        Some(frame) => frame,
+        None => return,
+    };
+
+    if let Probe::Reprobe(..) = probe {
+        log!(log::Level::Trace, "sending {}", probe.shown(privacy));
+    }
+
+    if let Err(e) = tx.send(&frame) {
+        log!(
+            log::Level::Trace,
+            "failed to send {}: {}",
+            probe.shown(privacy),
+            e
+        );
+    } else if let Some(capture) = capture {
+        capture.record(&frame);
     }
 }
 
 fn check_interface_connectivity(
-    interface: &NetworkInterface,
+    transport: Arc<dyn PacketTransport>,
     reconnect_limiter: &NetworkCommandLimiter,
+    shutdown: &Shutdown,
 ) {
-    loop {
-        thread::sleep(Duration::from_millis(100));
-        if !is_interface_connected(interface) {
+    while !shutdown.wait(Duration::from_millis(100)) {
+        if !transport.is_connected() {
             reconnect_limiter.run();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: SourceAddrs = SourceAddrs {
+        mac: MacAddr(0x02, 0, 0, 0, 0, 1),
+        ip: Ipv4Addr::new(10, 0, 0, 1),
+        ipv6: None,
+    };
+
+    fn swept(probes: &VecDeque<Probe>) -> Vec<Ipv4Addr> {
+        probes
+            .iter()
+            .filter_map(|probe| match probe {
+                Probe::Arp(ip) => Some(*ip),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn randomized_sweep_keeps_every_target() {
+        let ips: Vec<Ipv4Addr> = (2..=254)
+            .map(|last| Ipv4Addr::new(10, 0, 0, last))
+            .collect();
+        let cache = Mutex::new(MacCache::new());
+        let schedule = ReprobeSchedule::new(0, Duration::from_secs(5), Duration::from_secs(60));
+        let mut rng = rand::thread_rng();
+
+        let ordered = plan_sweep(&ips, &cache, &SOURCE, &schedule, false, &mut rng);
+        assert_eq!(swept(&ordered), ips);
+
+        let mut shuffled = swept(&plan_sweep(
+            &ips, &cache, &SOURCE, &schedule, true, &mut rng,
+        ));
+        assert_ne!(shuffled, ips);
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, ips);
+    }
+
+    #[test]
+    fn known_devices_are_left_out_of_sweeps_when_reprobed() {
+        let ips: Vec<Ipv4Addr> = (2..=5).map(|last| Ipv4Addr::new(10, 0, 0, last)).collect();
+        let mut cache = MacCache::new();
+        cache.record(
+            MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55),
+            Sighting::now(Some(IpAddr::V4(ips[1])), "eth0"),
+        );
+        let cache = Mutex::new(cache);
+        let mut rng = rand::thread_rng();
+
+        let reprobed = ReprobeSchedule::new(2, Duration::from_secs(5), Duration::from_secs(60));
+        let probes = plan_sweep(&ips, &cache, &SOURCE, &reprobed, false, &mut rng);
+        assert_eq!(swept(&probes), vec![ips[0], ips[2], ips[3]]);
+
+        let swept_only = ReprobeSchedule::new(0, Duration::from_secs(5), Duration::from_secs(60));
+        let probes = plan_sweep(&ips, &cache, &SOURCE, &swept_only, false, &mut rng);
+        assert_eq!(swept(&probes), ips);
+    }
+}
@@ -0,0 +1,54 @@
+use std::{convert::Infallible, fmt::Display, fs, io, path::Path, str::FromStr};
+
+use crate::privacy::REDACTED;
+
+/// Secret option, such as the API key, that is redacted when printed or logged
+///
+/// Use [`Secret::expose`] where the value itself is needed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Reads the secret from the file at path, as mounted by Docker, Kubernetes or systemd
+    ///
+    /// Surrounding whitespace, such as a trailing newline, is not part of the secret.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let value = contents.trim();
+
+        if value.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file is empty"));
+        }
+
+        Ok(Self::new(value))
+    }
+
+    /// The secret value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{REDACTED}")
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::new(s))
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    sync::{Condvar, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::error::ArpScannerErr;
+use crate::sync::lock;
+
+/// Stop flag shared between scanner threads
+///
+/// Threads sleep through [`Shutdown::wait`] instead of `thread::sleep` so that a stop request
+/// wakes them up immediately instead of after their full period.
+pub struct Shutdown {
+    stopped: Mutex<bool>,
+    cvar: Condvar,
+    /// Error of the thread that stopped the scanner, if it did not stop on request
+    failure: Mutex<Option<ArpScannerErr>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            stopped: Mutex::new(false),
+            cvar: Condvar::new(),
+            failure: Mutex::new(None),
+        }
+    }
+
+    /// Requests every waiting thread to stop
+    pub fn trigger(&self) {
+        let mut stopped = lock(&self.stopped);
+        *stopped = true;
+        self.cvar.notify_all();
+    }
+
+    /// Stops every thread because of error, only the first error is kept
+    ///
+    /// Errors after a stop was requested are ignored, channels may close while threads stop.
+    pub fn fail(&self, error: ArpScannerErr) {
+        let mut stopped = lock(&self.stopped);
+        if *stopped {
+            return;
+        }
+
+        *lock(&self.failure) = Some(error);
+        *stopped = true;
+        self.cvar.notify_all();
+    }
+
+    /// Takes the error the scanner was stopped with
+    pub fn take_failure(&self) -> Option<ArpScannerErr> {
+        lock(&self.failure).take()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *lock(&self.stopped)
+    }
+
+    /// Sleeps for timeout or until a stop is requested
+    /// Returns true if the scanner was stopped
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut stopped = lock(&self.stopped);
+
+        while !*stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            stopped = self
+                .cvar
+                .wait_timeout(stopped, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        *stopped
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use pnet::{
+    packet::{
+        arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket},
+        ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+        icmpv6::{Icmpv6Packet, Icmpv6Types},
+        ipv6::Ipv6Packet,
+        Packet,
+    },
+    util::MacAddr,
+};
+use pnet_datalink::NetworkInterface;
+
+use crate::ndp::{
+    gen_icmpv6_frame, gen_neighbor_advertisement, ALL_NODES, NA_FLAG_OVERRIDE, NA_FLAG_SOLICITED,
+};
+use crate::transport::{PacketReceiver, PacketSender, PacketTransport, RECEIVE_TIMEOUT};
+
+// Interface flags shared by Linux, BSD and macOS
+const IFF_UP: u32 = 0x1;
+const IFF_BROADCAST: u32 = 0x2;
+const IFF_RUNNING: u32 = 0x40;
+
+const ARP_PACKET_SIZE: usize = 28;
+const ETHERNET_HEADER_SIZE: usize = 14;
+
+/// Virtual host on a [`SimulatedLan`] that answers ARP requests for its ip
+/// while it is connected, and neighbor solicitations and all-nodes pings if it has an IPv6 address
+#[derive(Clone, Debug)]
+pub struct SimulatedHost {
+    pub mac: MacAddr,
+    pub ip: Ipv4Addr,
+    pub ipv6: Option<Ipv6Addr>,
+    /// Time after the LAN is created at which the host connects
+    pub joins_after: Duration,
+    /// Time after the LAN is created at which the host disconnects, if ever
+    pub leaves_after: Option<Duration>,
+}
+
+impl SimulatedHost {
+    /// Host that is connected for the whole lifetime of the LAN
+    pub fn new(mac: MacAddr, ip: Ipv4Addr) -> Self {
+        Self {
+            mac,
+            ip,
+            ipv6: None,
+            joins_after: Duration::ZERO,
+            leaves_after: None,
+        }
+    }
+
+    pub fn with_ipv6(mut self, ip: Ipv6Addr) -> Self {
+        self.ipv6 = Some(ip);
+        self
+    }
+
+    pub fn joining_after(mut self, delay: Duration) -> Self {
+        self.joins_after = delay;
+        self
+    }
+
+    pub fn leaving_after(mut self, delay: Duration) -> Self {
+        self.leaves_after = Some(delay);
+        self
+    }
+
+    fn is_connected(&self, elapsed: Duration) -> bool {
+        elapsed >= self.joins_after && self.leaves_after.map_or(true, |l| elapsed < l)
+    }
+}
+
+struct LanState {
+    hosts: Vec<SimulatedHost>,
+    created: Instant,
+}
+
+impl LanState {
+    fn connected_hosts(&self) -> impl Iterator<Item = &SimulatedHost> {
+        let elapsed = self.created.elapsed();
+        self.hosts.iter().filter(move |h| h.is_connected(elapsed))
+    }
+}
+
+/// In-memory network whose hosts join and leave on a schedule
+///
+/// Lets the whole scanner pipeline run without a real interface or CAP_NET_RAW.
+pub struct SimulatedLan {
+    interface: NetworkInterface,
+    state: Arc<LanState>,
+    connected: AtomicBool,
+}
+
+impl SimulatedLan {
+    /// Creates a LAN attached to interface; host schedules start now
+    pub fn new(interface: NetworkInterface, hosts: Vec<SimulatedHost>) -> Self {
+        Self {
+            interface,
+            state: Arc::new(LanState {
+                hosts,
+                created: Instant::now(),
+            }),
+            connected: AtomicBool::new(true),
+        }
+    }
+
+    /// Builds an interface that is up and broadcast capable, as selected by the scanner
+    pub fn interface(name: &str, mac: MacAddr, network: Ipv4Network) -> NetworkInterface {
+        NetworkInterface {
+            name: String::from(name),
+            description: String::from("simulated interface"),
+            index: 0,
+            mac: Some(mac),
+            // link-local address for neighbor discovery, derived from the mac like SLAAC would
+            ips: vec![
+                IpNetwork::V4(network),
+                IpNetwork::V6(Ipv6Network::new(link_local_ipv6(mac), 64).unwrap()),
+            ],
+            flags: IFF_UP | IFF_BROADCAST | IFF_RUNNING,
+        }
+    }
+
+    /// Number of hosts currently connected
+    pub fn connected_hosts(&self) -> usize {
+        self.state.connected_hosts().count()
+    }
+
+    /// Simulates the scanner's own link going down or coming back up
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::SeqCst);
+    }
+}
+
+impl PacketTransport for SimulatedLan {
+    fn interface(&self) -> &NetworkInterface {
+        &self.interface
+    }
+
+    fn open(&self) -> io::Result<(Box<dyn PacketSender>, Box<dyn PacketReceiver>)> {
+        let (tx, rx) = mpsc::channel();
+
+        Ok((
+            Box::new(SimulatedSender {
+                state: Arc::clone(&self.state),
+                replies: tx,
+            }),
+            Box::new(SimulatedReceiver {
+                frames: rx,
+                current: vec![],
+            }),
+        ))
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+}
+
+struct SimulatedSender {
+    state: Arc<LanState>,
+    replies: Sender<Vec<u8>>,
+}
+
+impl PacketSender for SimulatedSender {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let eth_packet = match EthernetPacket::new(frame) {
+            Some(packet) => packet,
+            None => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        };
+
+        let replies = if eth_packet.get_ethertype() == EtherTypes::Arp {
+            self.answer_arp(&eth_packet)?
+        } else if eth_packet.get_ethertype() == EtherTypes::Ipv6 {
+            self.answer_icmpv6(&eth_packet)?
+        } else {
+            vec![]
+        };
+
+        for reply in replies {
+            // receiver was dropped, nobody is listening anymore
+            if self.replies.send(reply).is_err() {
+                return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SimulatedSender {
+    fn answer_arp(&self, eth_packet: &EthernetPacket) -> io::Result<Vec<Vec<u8>>> {
+        let arp_packet = match ArpPacket::new(eth_packet.payload()) {
+            Some(packet) => packet,
+            None => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        };
+
+        if arp_packet.get_operation() != ArpOperations::Request {
+            return Ok(vec![]);
+        }
+
+        let destination = eth_packet.get_destination();
+        let target_ip = arp_packet.get_target_proto_addr();
+
+        Ok(self
+            .state
+            .connected_hosts()
+            .filter(|host| {
+                host.ip == target_ip && (destination.is_broadcast() || destination == host.mac)
+            })
+            .map(|host| {
+                gen_arp_reply(
+                    host,
+                    arp_packet.get_sender_hw_addr(),
+                    arp_packet.get_sender_proto_addr(),
+                )
+            })
+            .collect())
+    }
+
+    // Answers neighbor solicitations for a host's address and pings to all nodes
+    fn answer_icmpv6(&self, eth_packet: &EthernetPacket) -> io::Result<Vec<Vec<u8>>> {
+        let ip_packet = match Ipv6Packet::new(eth_packet.payload()) {
+            Some(packet) => packet,
+            None => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        };
+
+        let message = ip_packet.payload();
+        let icmp_type = match Icmpv6Packet::new(message) {
+            Some(packet) => packet.get_icmpv6_type(),
+            None => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        };
+
+        let (source_mac, source_ip) = (eth_packet.get_source(), ip_packet.get_source());
+        let hosts = self
+            .state
+            .connected_hosts()
+            .filter_map(|host| host.ipv6.map(|ip| (host, ip)));
+
+        if icmp_type == Icmpv6Types::NeighborSolicit {
+            let target: [u8; 16] = match message.get(8..24).and_then(|t| t.try_into().ok()) {
+                Some(target) => target,
+                None => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+            };
+            let target = Ipv6Addr::from(target);
+
+            Ok(hosts
+                .filter(|(_, ip)| *ip == target)
+                .map(|(host, ip)| {
+                    gen_neighbor_advertisement(
+                        host.mac,
+                        ip,
+                        source_mac,
+                        source_ip,
+                        NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE,
+                    )
+                })
+                .collect())
+        } else if icmp_type == Icmpv6Types::EchoRequest && ip_packet.get_destination() == ALL_NODES
+        {
+            Ok(hosts
+                .map(|(host, ip)| {
+                    // same identifier, sequence number and data as the request
+                    let mut reply = message.to_vec();
+                    reply[0] = Icmpv6Types::EchoReply.0;
+                    reply[2..4].fill(0);
+                    gen_icmpv6_frame(host.mac, source_mac, ip, source_ip, reply)
+                })
+                .collect())
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+struct SimulatedReceiver {
+    frames: Receiver<Vec<u8>>,
+    current: Vec<u8>,
+}
+
+impl PacketReceiver for SimulatedReceiver {
+    fn next(&mut self) -> io::Result<&[u8]> {
+        match self.frames.recv_timeout(RECEIVE_TIMEOUT) {
+            Ok(frame) => {
+                self.current = frame;
+                Ok(&self.current)
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::from(io::ErrorKind::TimedOut)),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        }
+    }
+}
+
+fn gen_arp_reply(host: &SimulatedHost, target_mac: MacAddr, target_ip: Ipv4Addr) -> Vec<u8> {
+    let mut arp_buf = [0u8; ARP_PACKET_SIZE];
+    let mut arp_packet = MutableArpPacket::new(&mut arp_buf).unwrap();
+
+    arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp_packet.set_protocol_type(EtherTypes::Ipv4);
+    arp_packet.set_hw_addr_len(6);
+    arp_packet.set_proto_addr_len(4);
+    arp_packet.set_operation(ArpOperations::Reply);
+    arp_packet.set_sender_hw_addr(host.mac);
+    arp_packet.set_sender_proto_addr(host.ip);
+    arp_packet.set_target_hw_addr(target_mac);
+    arp_packet.set_target_proto_addr(target_ip);
+
+    let mut eth_buf = vec![0u8; ETHERNET_HEADER_SIZE + ARP_PACKET_SIZE];
+    let mut eth_packet = MutableEthernetPacket::new(&mut eth_buf).unwrap();
+
+    eth_packet.set_destination(target_mac);
+    eth_packet.set_source(host.mac);
+    eth_packet.set_ethertype(EtherTypes::Arp);
+    eth_packet.set_payload(arp_packet.packet());
+
+    eth_buf
+}
+
+/// EUI-64 link-local address of mac
+pub fn link_local_ipv6(mac: MacAddr) -> Ipv6Addr {
+    let o = mac.octets();
+    Ipv6Addr::new(
+        0xfe80,
+        0,
+        0,
+        0,
+        (((o[0] ^ 0x02) as u16) << 8) | o[1] as u16,
+        ((o[2] as u16) << 8) | 0xff,
+        0xfe00 | o[3] as u16,
+        ((o[4] as u16) << 8) | o[5] as u16,
+    )
+}
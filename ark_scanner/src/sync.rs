@@ -0,0 +1,9 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Locks mutex, recovering it if a thread panicked while holding it
+///
+/// Guarded values are caches and flags that stay usable after a panic, so one failed thread
+/// does not take every other one down with it.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,261 @@
+use std::{fmt::Display, net::Ipv4Addr, str::FromStr};
+
+use ipnetwork::Ipv4Network;
+
+/// Entry of a target or exclusion list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetRange {
+    /// Every address of a cidr
+    Network(Ipv4Network),
+    /// Every address from start to end, both included
+    Range(Ipv4Addr, Ipv4Addr),
+    Host(Ipv4Addr),
+}
+
+impl TargetRange {
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let (first, last) = self.bounds();
+        (first..=last).contains(&u32::from(ip))
+    }
+
+    // First and last address as 32 bit words
+    fn bounds(&self) -> (u32, u32) {
+        match self {
+            TargetRange::Network(network) => {
+                (u32::from(network.network()), u32::from(network.broadcast()))
+            }
+            TargetRange::Range(start, end) => (u32::from(*start), u32::from(*end)),
+            TargetRange::Host(ip) => (u32::from(*ip), u32::from(*ip)),
+        }
+    }
+}
+
+impl FromStr for TargetRange {
+    type Err = String;
+
+    /// Parses a cidr (10.0.0.0/24), a range (10.0.0.10-10.0.0.50) or a single host (10.0.0.1)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some((start, end)) = s.split_once('-') {
+            let start: Ipv4Addr = start
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid range start in {s}"))?;
+            let end: Ipv4Addr = end
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid range end in {s}"))?;
+
+            if start > end {
+                return Err(format!("range {s} ends before it starts"));
+            }
+
+            return Ok(TargetRange::Range(start, end));
+        }
+
+        if s.contains('/') {
+            return s
+                .parse()
+                .map(TargetRange::Network)
+                .map_err(|_| format!("invalid cidr {s}"));
+        }
+
+        s.parse()
+            .map(TargetRange::Host)
+            .map_err(|_| format!("invalid ipv4 address {s}"))
+    }
+}
+
+impl Display for TargetRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetRange::Network(network) => write!(f, "{network}"),
+            TargetRange::Range(start, end) => write!(f, "{start}-{end}"),
+            TargetRange::Host(ip) => write!(f, "{ip}"),
+        }
+    }
+}
+
+/// IPv4 addresses that are scanned and counted
+///
+/// With no included ranges the whole interface subnet is targeted. Excluded ranges
+/// (gateways, printers, access points, ...) are never scanned nor counted.
+#[derive(Clone, Debug, Default)]
+pub struct TargetSet {
+    pub include: Vec<TargetRange>,
+    pub exclude: Vec<TargetRange>,
+}
+
+impl TargetSet {
+    pub fn new(include: Vec<TargetRange>, exclude: Vec<TargetRange>) -> Self {
+        Self { include, exclude }
+    }
+
+    /// Whether ip is targeted
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|range| range.contains(ip)))
+            && !self.exclude.iter().any(|range| range.contains(ip))
+    }
+
+    /// Returns the targeted ips inside subnet, excluding its network and broadcast addresses,
+    /// in ascending order
+    pub fn ips_within(&self, subnet: Ipv4Network) -> Vec<Ipv4Addr> {
+        let first = u32::from(subnet.network()).saturating_add(1);
+        let last = u32::from(subnet.broadcast()).saturating_sub(1);
+
+        let mut bounds: Vec<(u32, u32)> = if self.include.is_empty() {
+            vec![(first, last)]
+        } else {
+            // large ranges are clipped to the subnet before being expanded
+            self.include
+                .iter()
+                .map(|range| range.bounds())
+                .map(|(start, end)| (start.max(first), end.min(last)))
+                .filter(|(start, end)| start <= end)
+                .collect()
+        };
+
+        bounds.sort_unstable();
+
+        let mut ips = vec![];
+        let mut next = first;
+
+        for (start, end) in bounds {
+            // overlapping ranges are expanded once
+            for raw in start.max(next)..=end {
+                let ip = Ipv4Addr::from(raw);
+                if !self.exclude.iter().any(|range| range.contains(ip)) {
+                    ips.push(ip);
+                }
+            }
+            next = next.max(end.saturating_add(1));
+        }
+
+        ips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> Ipv4Addr {
+        ip.parse().unwrap()
+    }
+
+    fn ranges(ranges: &[&str]) -> Vec<TargetRange> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn subnet() -> Ipv4Network {
+        "10.0.0.0/24".parse().unwrap()
+    }
+
+    #[test]
+    fn ranges_are_parsed_and_formatted() {
+        for (range, parsed) in [
+            (
+                "10.0.0.0/28",
+                TargetRange::Network("10.0.0.0/28".parse().unwrap()),
+            ),
+            (
+                " 10.0.0.10 - 10.0.0.50 ",
+                TargetRange::Range(ip("10.0.0.10"), ip("10.0.0.50")),
+            ),
+            (
+                "10.0.0.7-10.0.0.7",
+                TargetRange::Range(ip("10.0.0.7"), ip("10.0.0.7")),
+            ),
+            ("10.0.0.1", TargetRange::Host(ip("10.0.0.1"))),
+        ] {
+            assert_eq!(range.parse::<TargetRange>(), Ok(parsed));
+            assert_eq!(parsed.to_string().parse::<TargetRange>(), Ok(parsed));
+        }
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        for range in [
+            "10.0.0.50-10.0.0.10",
+            "10.0.0.1-",
+            "x-10.0.0.1",
+            "10.0.0.0/33",
+            "10.0.0",
+            "fe80::1",
+            "",
+        ] {
+            assert!(range.parse::<TargetRange>().is_err(), "{range}");
+        }
+    }
+
+    #[test]
+    fn whole_subnet_is_targeted_without_ranges() {
+        let ips = TargetSet::default().ips_within(subnet());
+
+        assert_eq!(ips.len(), 254);
+        assert_eq!(ips.first(), Some(&ip("10.0.0.1")));
+        assert_eq!(ips.last(), Some(&ip("10.0.0.254")));
+    }
+
+    #[test]
+    fn ranges_are_clipped_to_the_subnet() {
+        let targets = TargetSet::new(ranges(&["0.0.0.0/0", "192.168.1.0/24"]), vec![]);
+        assert_eq!(targets.ips_within(subnet()).len(), 254);
+
+        let targets = TargetSet::new(
+            ranges(&["9.255.255.250-10.0.0.2", "10.0.0.254-10.0.1.5"]),
+            vec![],
+        );
+        assert_eq!(
+            targets.ips_within(subnet()),
+            vec![ip("10.0.0.1"), ip("10.0.0.2"), ip("10.0.0.254")]
+        );
+
+        let targets = TargetSet::new(ranges(&["192.168.1.0/24"]), vec![]);
+        assert!(targets.ips_within(subnet()).is_empty());
+    }
+
+    #[test]
+    fn overlapping_ranges_are_expanded_once() {
+        let targets = TargetSet::new(
+            ranges(&[
+                "10.0.0.5-10.0.0.8",
+                "10.0.0.0/29",
+                "10.0.0.6",
+                "10.0.0.8-10.0.0.9",
+            ]),
+            vec![],
+        );
+
+        let expected: Vec<Ipv4Addr> = (1..=9).map(|last| Ipv4Addr::new(10, 0, 0, last)).collect();
+        assert_eq!(targets.ips_within(subnet()), expected);
+    }
+
+    #[test]
+    fn exclusions_are_neither_scanned_nor_counted() {
+        let targets = TargetSet::new(
+            ranges(&["10.0.0.1-10.0.0.10"]),
+            ranges(&["10.0.0.1", "10.0.0.4-10.0.0.5", "10.0.0.8/30"]),
+        );
+
+        let scanned = vec![
+            ip("10.0.0.2"),
+            ip("10.0.0.3"),
+            ip("10.0.0.6"),
+            ip("10.0.0.7"),
+        ];
+        assert_eq!(targets.ips_within(subnet()), scanned);
+
+        for last in 0..=255 {
+            let ip = Ipv4Addr::new(10, 0, 0, last);
+            assert_eq!(targets.contains(ip), scanned.contains(&ip), "{ip}");
+        }
+
+        // exclusions also apply without included ranges
+        let targets = TargetSet::new(vec![], ranges(&["10.0.0.1"]));
+        assert!(!targets.contains(ip("10.0.0.1")));
+        assert!(targets.contains(ip("192.168.1.1")));
+        assert_eq!(targets.ips_within(subnet()).first(), Some(&ip("10.0.0.2")));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{io, time::Duration};
+
+use pnet_datalink::{Channel, DataLinkReceiver, DataLinkSender, NetworkInterface};
+
+use crate::network::is_interface_connected;
+
+/// How long a blocked read waits before returning a timeout,
+/// letting receiving threads check for shutdown
+pub const RECEIVE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Sending half of an opened transport
+pub trait PacketSender: Send {
+    /// Sends a full Ethernet frame
+    fn send(&mut self, frame: &[u8]) -> io::Result<()>;
+}
+
+/// Receiving half of an opened transport
+pub trait PacketReceiver: Send {
+    /// Blocks until the next Ethernet frame arrives
+    /// Returns an error of kind `TimedOut` if nothing arrived within [`RECEIVE_TIMEOUT`].
+    fn next(&mut self) -> io::Result<&[u8]>;
+}
+
+/// Source and sink of Ethernet frames for a single network interface
+///
+/// [`PnetTransport`] talks to a real interface, [`crate::simulation::SimulatedLan`]
+/// to an in-memory network.
+pub trait PacketTransport: Send + Sync {
+    /// Interface the transport sends and receives on
+    fn interface(&self) -> &NetworkInterface;
+
+    /// Opens a channel on the interface
+    fn open(&self) -> io::Result<(Box<dyn PacketSender>, Box<dyn PacketReceiver>)>;
+
+    /// Whether the interface is still connected to the network
+    fn is_connected(&self) -> bool;
+}
+
+/// Transport over a pnet datalink channel
+/// Opening it requires root or CAP_NET_RAW.
+pub struct PnetTransport {
+    interface: NetworkInterface,
+}
+
+impl PnetTransport {
+    pub fn new(interface: NetworkInterface) -> Self {
+        Self { interface }
+    }
+}
+
+impl PacketTransport for PnetTransport {
+    fn interface(&self) -> &NetworkInterface {
+        &self.interface
+    }
+
+    fn open(&self) -> io::Result<(Box<dyn PacketSender>, Box<dyn PacketReceiver>)> {
+        let channel = pnet_datalink::channel(
+            &self.interface,
+            pnet_datalink::Config {
+                read_timeout: Some(RECEIVE_TIMEOUT),
+                ..pnet_datalink::Config::default()
+            },
+        )?;
+
+        match channel {
+            Channel::Ethernet(tx, rx) => Ok((
+                Box::new(PnetSender {
+                    tx,
+                    interface: self.interface.clone(),
+                }),
+                Box::new(PnetReceiver { rx }),
+            )),
+            // this should never happen
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "datalink channel is not ethernet",
+            )),
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        is_interface_connected(&self.interface)
+    }
+}
+
+struct PnetSender {
+    tx: Box<dyn DataLinkSender>,
+    interface: NetworkInterface,
+}
+
+impl PacketSender for PnetSender {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self.tx.send_to(frame, Some(self.interface.clone())) {
+            Some(result) => result,
+            None => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "insufficient buffer space",
+            )),
+        }
+    }
+}
+
+struct PnetReceiver {
+    rx: Box<dyn DataLinkReceiver>,
+}
+
+impl PacketReceiver for PnetReceiver {
+    fn next(&mut self) -> io::Result<&[u8]> {
+        self.rx.next()
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use ark_scanner::{
+    cache_logger::DeviceCounts,
+    load_scanner_opts,
+    simulation::{SimulatedHost, SimulatedLan},
+    start_arp_scanner_with_transport, ConfigLayers, ScannerHandle, ScannerOptions,
+};
+use pnet_datalink::MacAddr;
+
+// Generous, counts usually change within a few seconds
+const DEADLINE: Duration = Duration::from_secs(30);
+
+fn options() -> ScannerOptions {
+    // the environment of the test process is ignored
+    let mut layers = ConfigLayers::with_env(HashMap::new());
+    for (key, value) in [
+        ("location", "lab"),
+        ("trace", "false"),
+        ("reconnect_cmd", "true"),
+        ("timing.mac_addr_timeout_secs", "1"),
+        ("timing.arp_scan_period_secs", "1"),
+        ("timing.mac_cache_log_period_secs", "1"),
+        ("timing.randomize_targets", "false"),
+        ("timing.reprobe_retries", "0"),
+        ("targets.include", "10.0.0.2-10.0.0.5"),
+        // counts are only logged locally
+        ("sinks.api_url", ""),
+    ] {
+        layers = layers.with_override(key, value, "test").unwrap();
+    }
+
+    load_scanner_opts(&layers).unwrap()
+}
+
+fn host(id: u8) -> SimulatedHost {
+    SimulatedHost::new(
+        MacAddr::new(0x02, 0, 0, 0, 0, id),
+        format!("10.0.0.{id}").parse().unwrap(),
+    )
+}
+
+/// Devices counted at the only location, lab
+fn device_count(counts: Vec<(String, DeviceCounts)>) -> u64 {
+    assert_eq!(counts.len(), 1);
+    assert_eq!(counts[0].0, "lab");
+    counts[0].1.total
+}
+
+/// Waits until scanner counts expected devices, fails past the deadline
+fn wait_for_count(scanner: &ScannerHandle, expected: u64) {
+    let start = Instant::now();
+
+    loop {
+        let count = device_count(scanner.device_counts());
+        if count == expected {
+            return;
+        }
+
+        assert!(
+            start.elapsed() < DEADLINE,
+            "counted {count} devices instead of {expected} after {DEADLINE:?}"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn hosts_joining_and_leaving_are_counted() {
+    let interface = SimulatedLan::interface(
+        "sim0",
+        MacAddr::new(0x02, 0, 0, 0, 0, 1),
+        "10.0.0.1/24".parse().unwrap(),
+    );
+    let lan = Arc::new(SimulatedLan::new(
+        interface,
+        vec![
+            host(2),
+            host(3).leaving_after(Duration::from_secs(3)),
+            host(4).leaving_after(Duration::from_secs(3)),
+            host(5).joining_after(Duration::from_secs(3)),
+        ],
+    ));
+
+    let scanner = start_arp_scanner_with_transport(options(), lan).unwrap();
+
+    // the hosts present from the start
+    wait_for_count(&scanner, 3);
+
+    // hosts that left expire once the cache janitor runs after the mac address timeout
+    wait_for_count(&scanner, 2);
+
+    assert!(!scanner.is_stopped());
+    assert!(scanner.stop().is_ok());
+}